@@ -0,0 +1,368 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use audiopus::{
+    coder::{Decoder, Encoder},
+    packet::Packet,
+    Application,
+    Channels,
+    MutSignals,
+    SampleRate,
+};
+
+// Opus only supports a few sample rates, 48kHz is the one the ogg granule positions refer to
+const OPUS_SAMPLE_RATE: u32 = 48_000;
+// 20ms
+const OPUS_FRAME_SIZE: usize = 960;
+const OPUS_MAX_PACKET_SIZE: usize = 4000;
+// 120ms, the longest frame a packet may contain
+const OPUS_MAX_FRAME_SIZE: usize = 5760;
+// Amplitudes in the MSC1767 waveform range from 0 to this
+const WAVEFORM_MAX: f32 = 1024.0;
+
+// Interleaved samples from -1.0 to 1.0
+#[derive(Clone, Debug)]
+pub struct Audio {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    // Only mono and stereo are supported
+    pub channels: usize,
+}
+
+impl Audio {
+    pub fn mono(samples: Vec<f32>, sample_rate: u32) -> Audio {
+        Audio { samples, sample_rate, channels: 1 }
+    }
+
+    // Mix down to mono
+    pub fn to_mono(&self) -> Audio {
+        if self.channels == 1 {
+            return self.clone();
+        }
+        let samples = self.samples
+            .chunks(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+            .collect();
+        Audio::mono(samples, self.sample_rate)
+    }
+
+    fn into_stereo_or_mono(self) -> Audio {
+        if self.channels <= 2 { self } else { self.to_mono() }
+    }
+
+    fn frame_count(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frame_count() as f64 / self.sample_rate as f64)
+    }
+
+    // Linear interpolation is plenty for speech and synthetic test sounds
+    pub fn resample(&self, sample_rate: u32) -> Audio {
+        if sample_rate == self.sample_rate || self.samples.is_empty() {
+            return Audio { sample_rate, ..self.clone() };
+        }
+        let ratio = self.sample_rate as f64 / sample_rate as f64;
+        let frames = self.frame_count();
+        let len = (frames as f64 / ratio) as usize;
+        let mut samples = Vec::with_capacity(len * self.channels);
+        for i in 0..len {
+            let position = i as f64 * ratio;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            for channel in 0..self.channels {
+                let current = self.samples[index * self.channels + channel];
+                let next = if index + 1 < frames { self.samples[(index + 1) * self.channels + channel] } else { current };
+                samples.push(current + (next - current) * fraction);
+            }
+        }
+        Audio { samples, sample_rate, channels: self.channels }
+    }
+
+    // Peak amplitude per bar, scaled to the loudest bar like other clients do
+    pub fn waveform(&self, bars: usize) -> Vec<u16> {
+        let frames = self.frame_count();
+        if frames == 0 {
+            return vec![0; bars];
+        }
+        let peaks: Vec<f32> = (0..bars).map(|bar| {
+            let start = bar * frames / bars;
+            let end = ((bar + 1) * frames / bars).max(start + 1).min(frames);
+            self.samples[start * self.channels..end * self.channels].iter().fold(0.0f32, |max, s| max.max(s.abs()))
+        }).collect();
+        let loudest = peaks.iter().fold(0.0f32, |max, p| max.max(*p));
+        peaks.iter().map(|p| if loudest > 0.0 { (p / loudest * WAVEFORM_MAX) as u16 } else { 0 }).collect()
+    }
+
+    fn samples_i16(&self) -> Vec<i16> {
+        self.samples.iter().map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).collect()
+    }
+
+    pub fn to_wav(&self) -> Vec<u8> {
+        let channels = self.channels as u16;
+        let block_align = channels * 2;
+        let data_len = self.samples.len() as u32 * 2;
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&channels.to_le_bytes());
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes()); // byte rate
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in self.samples_i16() {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+
+    pub fn to_mp3(&self) -> anyhow::Result<Vec<u8>> {
+        let mut builder = mp3lame_encoder::Builder::new().ok_or(anyhow!("Failed to create MP3 encoder"))?;
+        builder.set_num_channels(self.channels as u8).map_err(|e| anyhow!("{e:?}"))?;
+        builder.set_sample_rate(self.sample_rate).map_err(|e| anyhow!("{e:?}"))?;
+        builder.set_brate(mp3lame_encoder::Bitrate::Kbps128).map_err(|e| anyhow!("{e:?}"))?;
+        let mut encoder = builder.build().map_err(|e| anyhow!("{e:?}"))?;
+
+        let samples = self.samples_i16();
+        let mut mp3 = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(self.frame_count()));
+        if self.channels == 1 {
+            encoder.encode_to_vec(mp3lame_encoder::MonoPcm(&samples), &mut mp3)
+        } else {
+            encoder.encode_to_vec(mp3lame_encoder::InterleavedPcm(&samples), &mut mp3)
+        }.map_err(|e| anyhow!("{e:?}"))?;
+        encoder.flush_to_vec::<mp3lame_encoder::FlushNoGap>(&mut mp3).map_err(|e| anyhow!("{e:?}"))?;
+        Ok(mp3)
+    }
+
+    // Ogg/Opus as sent by clients for voice messages. Voip suits speech, Audio keeps tones and
+    // noise intact.
+    pub fn to_ogg_opus(&self, application: Application) -> anyhow::Result<Vec<u8>> {
+        let audio = self.resample(OPUS_SAMPLE_RATE);
+        let channels = if self.channels == 2 { Channels::Stereo } else { Channels::Mono };
+        let encoder = Encoder::new(SampleRate::Hz48000, channels, application)?;
+        let pre_skip = encoder.lookahead()? as u16;
+
+        let mut ogg = OggWriter::new(rand::random());
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(self.channels as u8);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&self.sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+        ogg.write_page(&[head], 0, OGG_BEGINNING_OF_STREAM);
+
+        let vendor = b"matrix-wip-bot";
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&0u32.to_le_bytes()); // user comments
+        ogg.write_page(&[tags], 0, 0);
+
+        let mut packets = Vec::new();
+        let mut lacing_values = 0;
+        let mut granule_position = pre_skip as u64;
+        for frame in audio.samples.chunks(OPUS_FRAME_SIZE * self.channels) {
+            let mut padded = frame.to_vec();
+            padded.resize(OPUS_FRAME_SIZE * self.channels, 0.0);
+            let mut packet = vec![0u8; OPUS_MAX_PACKET_SIZE];
+            let len = encoder.encode_float(&padded, &mut packet)?;
+            packet.truncate(len);
+
+            let packet_lacing_values = len / 255 + 1;
+            if lacing_values + packet_lacing_values > 255 {
+                ogg.write_page(&packets, granule_position, 0);
+                packets.clear();
+                lacing_values = 0;
+            }
+            lacing_values += packet_lacing_values;
+            packets.push(packet);
+            // The granule position of the last page tells players how much of the padding to drop
+            granule_position += (frame.len() / self.channels) as u64;
+        }
+        ogg.write_page(&packets, granule_position, OGG_END_OF_STREAM);
+        Ok(ogg.finish())
+    }
+}
+
+// Decode WAV, Ogg/Opus or MP3, whatever the data looks like
+pub fn decode(data: &[u8]) -> anyhow::Result<Audio> {
+    if data.starts_with(b"RIFF") {
+        decode_wav(data)
+    } else if data.starts_with(b"OggS") {
+        decode_ogg_opus(data)
+    } else {
+        decode_mp3(data)
+    }
+}
+
+fn decode_wav(wav: &[u8]) -> anyhow::Result<Audio> {
+    if wav.len() < 12 || &wav[8..12] != b"WAVE" {
+        bail!("Not a WAV file");
+    }
+    let mut format = None;
+    let mut position = 12;
+    while position + 8 <= wav.len() {
+        let id = &wav[position..position + 4];
+        let len = u32::from_le_bytes(wav[position + 4..position + 8].try_into()?) as usize;
+        let body = &wav[position + 8..(position + 8 + len).min(wav.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let audio_format = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+                let sample_rate = u32::from_le_bytes(body[4..8].try_into()?);
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                format = Some((audio_format, channels, sample_rate, bits));
+            }
+            b"data" => {
+                let (audio_format, channels, sample_rate, bits) = format.ok_or(anyhow!("WAV data before format"))?;
+                let samples = match (audio_format, bits) {
+                    (1, 16) => body.chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / i16::MAX as f32).collect(),
+                    (3, 32) => body.chunks_exact(4).map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]])).collect(),
+                    _ => bail!("Unsupported WAV format {audio_format} with {bits} bits"),
+                };
+                return Ok(Audio { samples, sample_rate, channels: channels.max(1) }.into_stereo_or_mono());
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        position += 8 + len + len % 2;
+    }
+    bail!("No data in WAV file")
+}
+
+fn decode_ogg_opus(ogg: &[u8]) -> anyhow::Result<Audio> {
+    let mut packets = ogg_packets(ogg)?.into_iter();
+    let head = packets.next().ok_or(anyhow!("Empty ogg stream"))?;
+    if !head.starts_with(b"OpusHead") || head.len() < 19 {
+        bail!("Not an Opus stream");
+    }
+    let channels = head[9] as usize;
+    let pre_skip = u16::from_le_bytes([head[10], head[11]]) as usize;
+    let opus_channels = match channels {
+        1 => Channels::Mono,
+        2 => Channels::Stereo,
+        _ => bail!("Unsupported channel count {channels}"),
+    };
+    let mut decoder = Decoder::new(SampleRate::Hz48000, opus_channels)?;
+
+    let mut samples = Vec::new();
+    // Skip OpusTags
+    for packet in packets.skip(1).filter(|packet| !packet.is_empty()) {
+        let mut output = vec![0.0f32; OPUS_MAX_FRAME_SIZE * channels];
+        let packet: Packet = packet.as_slice().try_into()?;
+        let signals: MutSignals<f32> = (&mut output).try_into()?;
+        let len = decoder.decode_float(Some(packet), signals, false)?;
+        samples.extend_from_slice(&output[..len * channels]);
+    }
+    let samples = samples.split_off((pre_skip * channels).min(samples.len()));
+    Ok(Audio { samples, sample_rate: OPUS_SAMPLE_RATE, channels })
+}
+
+fn decode_mp3(mp3: &[u8]) -> anyhow::Result<Audio> {
+    let mut decoder = minimp3::Decoder::new(mp3);
+    let mut samples = Vec::new();
+    let mut format = None;
+    loop {
+        match decoder.next_frame() {
+            Ok(frame) => {
+                format.get_or_insert((frame.sample_rate as u32, frame.channels));
+                samples.extend(frame.data.iter().map(|s| *s as f32 / i16::MAX as f32));
+            }
+            Err(minimp3::Error::Eof) => break,
+            Err(e) => bail!("Failed to decode MP3: {e}"),
+        }
+    }
+    let (sample_rate, channels) = format.ok_or(anyhow!("Unsupported audio format"))?;
+    Ok(Audio { samples, sample_rate, channels }.into_stereo_or_mono())
+}
+
+// Reassemble the packets of a single logical ogg stream
+fn ogg_packets(ogg: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut packets = Vec::new();
+    let mut current = Vec::new();
+    let mut position = 0;
+    while position + 27 <= ogg.len() {
+        if &ogg[position..position + 4] != b"OggS" {
+            bail!("Invalid ogg page at {position}");
+        }
+        let segment_count = ogg[position + 26] as usize;
+        let lacing_start = position + 27;
+        let mut data_position = lacing_start + segment_count;
+        let lacing = ogg.get(lacing_start..data_position).ok_or(anyhow!("Truncated ogg page"))?;
+        for len in lacing {
+            let len = *len as usize;
+            current.extend_from_slice(ogg.get(data_position..data_position + len).ok_or(anyhow!("Truncated ogg page"))?);
+            data_position += len;
+            // Packets continue on the next lacing value if it is 255
+            if len < 255 {
+                packets.push(std::mem::take(&mut current));
+            }
+        }
+        position = data_position;
+    }
+    Ok(packets)
+}
+
+const OGG_BEGINNING_OF_STREAM: u8 = 0x02;
+const OGG_END_OF_STREAM: u8 = 0x04;
+
+struct OggWriter {
+    data: Vec<u8>,
+    serial: u32,
+    sequence: u32,
+}
+
+impl OggWriter {
+    fn new(serial: u32) -> Self {
+        OggWriter { data: Vec::new(), serial, sequence: 0 }
+    }
+
+    // Packets need to fit into the 255 lacing values of a single page
+    fn write_page(&mut self, packets: &[Vec<u8>], granule_position: u64, flags: u8) {
+        let mut lacing = Vec::new();
+        for packet in packets {
+            lacing.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+            lacing.push((packet.len() % 255) as u8);
+        }
+        let start = self.data.len();
+        self.data.extend_from_slice(b"OggS");
+        self.data.push(0); // version
+        self.data.push(flags);
+        self.data.extend_from_slice(&granule_position.to_le_bytes());
+        self.data.extend_from_slice(&self.serial.to_le_bytes());
+        self.data.extend_from_slice(&self.sequence.to_le_bytes());
+        self.data.extend_from_slice(&0u32.to_le_bytes()); // checksum, filled in below
+        self.data.push(lacing.len() as u8);
+        self.data.extend_from_slice(&lacing);
+        for packet in packets {
+            self.data.extend_from_slice(packet);
+        }
+        let checksum = ogg_crc32(&self.data[start..]);
+        self.data[start + 22..start + 26].copy_from_slice(&checksum.to_le_bytes());
+        self.sequence += 1;
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+// Unlike the zip/png one, ogg uses the non-reflected CRC-32 without final inversion
+fn ogg_crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 { (crc << 1) ^ 0x04c11db7 } else { crc << 1 };
+        }
+    }
+    crc
+}
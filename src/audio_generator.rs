@@ -0,0 +1,68 @@
+use std::f32::consts::PI;
+
+use crate::audio::Audio;
+
+const SAMPLE_RATE: u32 = 48_000;
+// Not too loud
+const AMPLITUDE: f32 = 0.5;
+// Short fade in and out to avoid clicks
+const FADE_SECONDS: f32 = 0.01;
+
+fn sample_count(seconds: f32) -> usize {
+    (seconds.max(0.0) * SAMPLE_RATE as f32) as usize
+}
+
+fn fade(samples: &mut [f32]) {
+    let fade_len = sample_count(FADE_SECONDS).min(samples.len() / 2);
+    let len = samples.len();
+    for (i, sample) in samples[..fade_len].iter_mut().enumerate() {
+        *sample *= i as f32 / fade_len as f32;
+    }
+    for (i, sample) in samples[len - fade_len..].iter_mut().rev().enumerate() {
+        *sample *= i as f32 / fade_len as f32;
+    }
+}
+
+pub fn sine(frequency: f32, seconds: f32) -> Audio {
+    sweep(frequency, frequency, seconds)
+}
+
+// Sine with the frequency changing exponentially, so every octave takes the same time
+pub fn sweep(from_frequency: f32, to_frequency: f32, seconds: f32) -> Audio {
+    let count = sample_count(seconds);
+    let mut phase = 0.0f32;
+    let mut samples: Vec<f32> = (0..count).map(|i| {
+        let t = i as f32 / count.max(1) as f32;
+        let frequency = from_frequency * (to_frequency / from_frequency).powf(t);
+        let sample = phase.sin() * AMPLITUDE;
+        phase = (phase + 2.0 * PI * frequency / SAMPLE_RATE as f32) % (2.0 * PI);
+        sample
+    }).collect();
+    fade(&mut samples);
+    Audio::mono(samples, SAMPLE_RATE)
+}
+
+pub fn white_noise(seconds: f32) -> Audio {
+    let mut samples: Vec<f32> = (0..sample_count(seconds))
+        .map(|_| rand::random_range(-1.0..1.0) * AMPLITUDE)
+        .collect();
+    fade(&mut samples);
+    Audio::mono(samples, SAMPLE_RATE)
+}
+
+pub fn silence(seconds: f32) -> Audio {
+    Audio::mono(vec![0.0; sample_count(seconds)], SAMPLE_RATE)
+}
+
+// Tone on the left channel for the first half, then on the right channel
+pub fn channel_test(frequency: f32, seconds: f32) -> Audio {
+    let half = sine(frequency, seconds / 2.0).samples;
+    let mut samples = Vec::with_capacity(half.len() * 4);
+    for sample in &half {
+        samples.extend_from_slice(&[*sample, 0.0]);
+    }
+    for sample in &half {
+        samples.extend_from_slice(&[0.0, *sample]);
+    }
+    Audio { samples, sample_rate: SAMPLE_RATE, channels: 2 }
+}
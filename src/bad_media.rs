@@ -0,0 +1,82 @@
+use crate::image_generator::png_chunk;
+
+// Deflate stream writer, bits are packed starting at the least significant bit
+struct DeflateWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    bit_count: u32,
+}
+
+impl DeflateWriter {
+    fn new() -> Self {
+        DeflateWriter { bytes: Vec::new(), current: 0, bit_count: 0 }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        self.current |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.current as u8);
+            self.current >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting with their most significant bit
+    fn code(&mut self, code: u32, length: u32) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.bits(reversed, length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push(self.current as u8);
+        }
+        self.bytes
+    }
+}
+
+// Zlib stream of `len` zero bytes, using fixed Huffman back-references of maximum length
+fn zlib_zeros(len: usize) -> Vec<u8> {
+    let mut w = DeflateWriter::new();
+    w.bits(1, 1); // BFINAL
+    w.bits(1, 2); // BTYPE: fixed Huffman
+    let literal_zero = |w: &mut DeflateWriter| w.code(0x30, 8);
+    if len > 0 {
+        literal_zero(&mut w);
+        let remaining = len - 1;
+        for _ in 0..remaining / 258 {
+            w.code(0xc5, 8); // Length 258
+            w.code(0, 5); // Distance 1
+        }
+        for _ in 0..remaining % 258 {
+            literal_zero(&mut w);
+        }
+    }
+    w.code(0, 7); // End of block
+
+    let mut result = vec![0x78, 0x01];
+    result.extend_from_slice(&w.finish());
+    let adler = ((len % 65521) as u32) << 16 | 1;
+    result.extend_from_slice(&adler.to_be_bytes());
+    result
+}
+
+// Small black 1-bit grayscale PNG that decodes to huge dimensions
+pub fn create_png_bomb(width: u32, height: u32) -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[1, 0, 0, 0, 0]); // 1 bit grayscale, no interlacing
+    png_chunk(&mut png, b"IHDR", &ihdr);
+    let row_len = (width as usize).div_ceil(8) + 1;
+    png_chunk(&mut png, b"IDAT", &zlib_zeros(row_len * height as usize));
+    png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Cut off the data at the given fraction, so the file header still looks fine
+pub fn truncate(data: &[u8], fraction: f64) -> Vec<u8> {
+    data[..(data.len() as f64 * fraction) as usize].to_vec()
+}
@@ -0,0 +1,283 @@
+// Placeholder hashes for images: BlurHash (https://github.com/woltapp/blurhash/blob/master/Algorithm.md)
+// and ThumbHash (https://github.com/evanw/thumbhash), both computed from RGBA pixels.
+
+use std::f64::consts::PI;
+
+const BASE83_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaceholderMode {
+    Real,
+    // Valid hash of a different image
+    Mismatch,
+    Invalid,
+    None,
+}
+
+impl PlaceholderMode {
+    pub fn parse(mode: &str) -> Option<PlaceholderMode> {
+        match mode.to_ascii_lowercase().as_str() {
+            "real" => Some(PlaceholderMode::Real),
+            "mismatch" => Some(PlaceholderMode::Mismatch),
+            "invalid" => Some(PlaceholderMode::Invalid),
+            "none" => Some(PlaceholderMode::None),
+            _ => None,
+        }
+    }
+}
+
+// Both hashes only look at low frequencies, so there's no need to process more pixels than this
+const MAX_HASH_INPUT_SIZE: usize = 100;
+
+fn encode_base83(value: u32, length: u32, result: &mut String) {
+    for i in 1..=length {
+        let digit = (value / 83u32.pow(length - i)) % 83;
+        result.push(BASE83_CHARS[digit as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    if v <= 0.0031308 {
+        (v * 12.92 * 255.0 + 0.5) as u32
+    } else {
+        ((1.055 * v.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+    }
+}
+
+fn sign_pow(value: f64, exp: f64) -> f64 {
+    value.abs().powf(exp).copysign(value)
+}
+
+// Box-filter the image down so it fits into max_size x max_size
+pub fn downscale_rgba(rgba: &[u8], width: usize, height: usize, max_size: usize) -> (Vec<u8>, usize, usize) {
+    if width <= max_size && height <= max_size {
+        return (rgba.to_vec(), width, height);
+    }
+    let scale = width.max(height) as f64 / max_size as f64;
+    let new_width = ((width as f64 / scale).round() as usize).max(1);
+    let new_height = ((height as f64 / scale).round() as usize).max(1);
+    let mut result = Vec::with_capacity(new_width * new_height * 4);
+    for y in 0..new_height {
+        let y_start = y * height / new_height;
+        let y_end = ((y + 1) * height / new_height).max(y_start + 1);
+        for x in 0..new_width {
+            let x_start = x * width / new_width;
+            let x_end = ((x + 1) * width / new_width).max(x_start + 1);
+            let mut sum = [0usize; 4];
+            for sy in y_start..y_end {
+                for sx in x_start..x_end {
+                    let i = (sy * width + sx) * 4;
+                    for c in 0..4 {
+                        sum[c] += rgba[i + c] as usize;
+                    }
+                }
+            }
+            let count = (y_end - y_start) * (x_end - x_start);
+            result.extend(sum.iter().map(|s| (s / count) as u8));
+        }
+    }
+    (result, new_width, new_height)
+}
+
+pub fn blurhash(rgba: &[u8], width: usize, height: usize, components_x: u32, components_y: u32) -> String {
+    let (rgba, width, height) = downscale_rgba(rgba, width, height, MAX_HASH_INPUT_SIZE);
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0; 3];
+            for y in 0..height {
+                let basis_y = (PI * j as f64 * y as f64 / height as f64).cos();
+                for x in 0..width {
+                    let basis = normalisation * (PI * i as f64 * x as f64 / width as f64).cos() * basis_y;
+                    let p = (y * width + x) * 4;
+                    for c in 0..3 {
+                        factor[c] += basis * srgb_to_linear(rgba[p + c]);
+                    }
+                }
+            }
+            let scale = 1.0 / (width * height) as f64;
+            factors.push(factor.map(|f| f * scale));
+        }
+    }
+
+    let mut hash = String::new();
+    encode_base83((components_x - 1) + (components_y - 1) * 9, 1, &mut hash);
+
+    let ac = &factors[1..];
+    let maximum_value = if ac.is_empty() {
+        encode_base83(0, 1, &mut hash);
+        1.0
+    } else {
+        let actual_maximum = ac.iter().flatten().fold(0.0f64, |m, v| m.max(v.abs()));
+        let quantised_maximum = (actual_maximum * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        encode_base83(quantised_maximum, 1, &mut hash);
+        (quantised_maximum + 1) as f64 / 166.0
+    };
+
+    let dc = factors[0];
+    encode_base83((linear_to_srgb(dc[0]) << 16) + (linear_to_srgb(dc[1]) << 8) + linear_to_srgb(dc[2]), 4, &mut hash);
+
+    for factor in ac {
+        let quantised = factor.map(|f| (sign_pow(f / maximum_value, 0.5) * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32);
+        encode_base83(quantised[0] * 19 * 19 + quantised[1] * 19 + quantised[2], 2, &mut hash);
+    }
+    hash
+}
+
+pub fn thumbhash(rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let (rgba, w, h) = downscale_rgba(rgba, width, height, MAX_HASH_INPUT_SIZE);
+    let pixel_count = w * h;
+
+    let (mut avg_r, mut avg_g, mut avg_b, mut avg_a) = (0.0, 0.0, 0.0, 0.0);
+    for p in rgba.chunks(4) {
+        let alpha = p[3] as f64 / 255.0;
+        avg_r += alpha / 255.0 * p[0] as f64;
+        avg_g += alpha / 255.0 * p[1] as f64;
+        avg_b += alpha / 255.0 * p[2] as f64;
+        avg_a += alpha;
+    }
+    if avg_a > 0.0 {
+        avg_r /= avg_a;
+        avg_g /= avg_a;
+        avg_b /= avg_a;
+    }
+
+    let has_alpha = avg_a < pixel_count as f64;
+    let l_limit = if has_alpha { 5.0 } else { 7.0 };
+    let lx = ((l_limit * w as f64 / w.max(h) as f64).round() as usize).max(1);
+    let ly = ((l_limit * h as f64 / w.max(h) as f64).round() as usize).max(1);
+
+    let mut l = Vec::with_capacity(pixel_count);
+    let mut p = Vec::with_capacity(pixel_count);
+    let mut q = Vec::with_capacity(pixel_count);
+    let mut a = Vec::with_capacity(pixel_count);
+    for px in rgba.chunks(4) {
+        let alpha = px[3] as f64 / 255.0;
+        let r = avg_r * (1.0 - alpha) + alpha / 255.0 * px[0] as f64;
+        let g = avg_g * (1.0 - alpha) + alpha / 255.0 * px[1] as f64;
+        let b = avg_b * (1.0 - alpha) + alpha / 255.0 * px[2] as f64;
+        l.push((r + g + b) / 3.0);
+        p.push((r + g) / 2.0 - b);
+        q.push(r - g);
+        a.push(alpha);
+    }
+
+    let encode_channel = |channel: &[f64], nx: usize, ny: usize| {
+        let mut dc = 0.0;
+        let mut ac = Vec::new();
+        let mut scale: f64 = 0.0;
+        for cy in 0..ny {
+            let mut cx = 0;
+            while cx * ny < nx * (ny - cy) {
+                let fx: Vec<f64> = (0..w).map(|x| (PI / w as f64 * cx as f64 * (x as f64 + 0.5)).cos()).collect();
+                let mut f = 0.0;
+                for y in 0..h {
+                    let fy = (PI / h as f64 * cy as f64 * (y as f64 + 0.5)).cos();
+                    for x in 0..w {
+                        f += channel[x + y * w] * fx[x] * fy;
+                    }
+                }
+                f /= pixel_count as f64;
+                if cx > 0 || cy > 0 {
+                    ac.push(f);
+                    scale = scale.max(f.abs());
+                } else {
+                    dc = f;
+                }
+                cx += 1;
+            }
+        }
+        if scale > 0.0 {
+            for f in ac.iter_mut() {
+                *f = 0.5 + 0.5 / scale * *f;
+            }
+        }
+        (dc, ac, scale)
+    };
+
+    let (l_dc, l_ac, l_scale) = encode_channel(&l, lx.max(3), ly.max(3));
+    let (p_dc, p_ac, p_scale) = encode_channel(&p, 3, 3);
+    let (q_dc, q_ac, q_scale) = encode_channel(&q, 3, 3);
+    let alpha_channel = if has_alpha { Some(encode_channel(&a, 5, 5)) } else { None };
+
+    let is_landscape = w > h;
+    let header24 = (63.0 * l_dc).round() as u32
+        | ((31.5 + 31.5 * p_dc).round() as u32) << 6
+        | ((31.5 + 31.5 * q_dc).round() as u32) << 12
+        | ((31.0 * l_scale).round() as u32) << 18
+        | (has_alpha as u32) << 23;
+    let header16 = (if is_landscape { ly } else { lx }) as u32
+        | ((63.0 * p_scale).round() as u32) << 3
+        | ((63.0 * q_scale).round() as u32) << 9
+        | (is_landscape as u32) << 15;
+    let mut hash = vec![
+        (header24 & 255) as u8,
+        ((header24 >> 8) & 255) as u8,
+        (header24 >> 16) as u8,
+        (header16 & 255) as u8,
+        (header16 >> 8) as u8,
+    ];
+
+    let mut acs = vec![l_ac, p_ac, q_ac];
+    if let Some((a_dc, a_ac, a_scale)) = alpha_channel {
+        hash.push((15.0 * a_dc).round() as u8 | ((15.0 * a_scale).round() as u8) << 4);
+        acs.push(a_ac);
+    }
+    let ac_start = hash.len();
+    for (ac_index, f) in acs.iter().flatten().enumerate() {
+        let index = ac_start + (ac_index >> 1);
+        if index >= hash.len() {
+            hash.push(0);
+        }
+        hash[index] |= ((15.0 * f).round() as u8) << ((ac_index & 1) << 2);
+    }
+    hash
+}
+
+// Smooth gradient between two random colors, to get placeholder hashes that have nothing to do
+// with the actual image
+pub fn random_gradient_rgba(width: usize, height: usize) -> Vec<u8> {
+    let from: [u8; 3] = rand::random();
+    let to: [u8; 3] = rand::random();
+    let mut result = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let t = (x + y) as f64 / (width + height).max(2) as f64;
+            for c in 0..3 {
+                result.push((from[c] as f64 * (1.0 - t) + to[c] as f64 * t) as u8);
+            }
+            result.push(255);
+        }
+    }
+    result
+}
+
+pub fn blurhash_for(mode: PlaceholderMode, rgba: &[u8], width: usize, height: usize) -> Option<String> {
+    match mode {
+        PlaceholderMode::Real => Some(blurhash(rgba, width, height, 4, 3)),
+        PlaceholderMode::Mismatch => Some(blurhash(&random_gradient_rgba(32, 32), 32, 32, 4, 3)),
+        PlaceholderMode::Invalid => Some("Not a blurhash!".to_string()),
+        PlaceholderMode::None => None,
+    }
+}
+
+pub fn thumbhash_for(mode: PlaceholderMode, rgba: &[u8], width: usize, height: usize) -> Option<Vec<u8>> {
+    match mode {
+        PlaceholderMode::Real => Some(thumbhash(rgba, width, height)),
+        PlaceholderMode::Mismatch => Some(thumbhash(&random_gradient_rgba(32, 32), 32, 32)),
+        // Too short to even contain the header
+        PlaceholderMode::Invalid => Some(vec![0xff, 0xff, 0xff]),
+        PlaceholderMode::None => None,
+    }
+}
@@ -1,17 +1,25 @@
 use std::{
     self, cmp, str::SplitWhitespace,
+    sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
-    path::PathBuf,
 };
 use tokio::time::sleep;
+use audiopus::Application;
 use chrono::Utc;
 use config::Config;
 use log::{trace, debug, warn, error};
 use matrix_sdk::{
+    Client,
     Room,
+    deserialized_responses::RawSyncOrStrippedState,
+    media::{MediaFormat, MediaRequestParameters},
     ruma::{
         assign,
-        api::client::room::create_room,
+        api::client::{
+            authenticated_media::{get_content_thumbnail, get_media_config},
+            room::create_room,
+            relations::get_relating_events,
+        },
         events::{
             AnyTimelineEvent,
             AnyMessageLikeEvent,
@@ -20,12 +28,19 @@ use matrix_sdk::{
             room::{
                 message::{
                     OriginalSyncRoomMessageEvent,
+                    OriginalRoomMessageEvent,
                     RoomMessageEventContent,
                     AddMentions,
                     ReplyWithinThread,
                     Relation,
+                    MessageFormat,
                     ImageMessageEventContent,
                     AudioMessageEventContent,
+                    AudioInfo,
+                    FileMessageEventContent,
+                    FileInfo,
+                    VideoMessageEventContent,
+                    VideoInfo,
                     MessageType,
                 },
                 ImageInfo,
@@ -33,27 +48,58 @@ use matrix_sdk::{
                 ThumbnailInfo,
             },
             reaction::ReactionEventContent,
+            message::TextContentBlock,
+            poll::{
+                start::{PollStartEventContent, PollContentBlock, PollAnswers, PollAnswer, PollKind},
+                response::{PollResponseEventContent, SelectionsContentBlock},
+                end::PollEndEventContent,
+                unstable_start::{
+                    UnstablePollStartEventContent,
+                    NewUnstablePollStartEventContent,
+                    UnstablePollStartContentBlock,
+                    UnstablePollAnswers,
+                    UnstablePollAnswer,
+                },
+                unstable_response::UnstablePollResponseEventContent,
+                unstable_end::UnstablePollEndEventContent,
+            },
             relation::{InReplyTo, Annotation},
             sticker::{StickerEventContent, StickerMediaSource},
         },
+        media::Method,
         RoomVersionId,
-        serde::Raw,
+        OwnedEventId,
+        OwnedMxcUri,
+        OwnedUserId,
+        UInt,
+        serde::{Raw, Base64},
     },
 };
 use rand;
 
-use piper_rs::synth::PiperSpeechSynthesizer;
-use tempfile::NamedTempFile;
 
 use crate::{
     users::{is_user_vip, is_user_trusted, is_user_trusted_not_vip},
-    image_generator,
+    image_generator::{self, AnimationFormat, ImageEncoding, ImageFormat, ImagePattern},
+    file_generator,
+    video_generator,
+    bad_media,
+    blurhash::{self, PlaceholderMode},
+    audio,
+    audio_generator,
+    tts::Tts,
+    transcribe::Transcriber,
+    upload_cache::UploadCache,
+    media_info,
     WipContext,
     bridge::{BridgeStateContent, BridgeProtocol},
+    image_pack::{self, ImagePack, PackImage, PackInfo, PackUsage, RoomImagePackContent, UserImagePackContent},
 };
 
 mod spam;
-use spam::{TEXT_SPAM, STICKER_SPAM};
+use spam::{TEXT_SPAM, POLL_ANSWERS, REACTION_EMOJI};
+mod formatting;
+use formatting::FORMAT_SAMPLES;
 
 const FAKE_BRIDGE_KEY: &str = "de.spiritcroc.wipbot";
 
@@ -64,23 +110,68 @@ const HELP: &str = "- `!help` - Print this help\n\
                     - `!room` - Show current room ID\n\
                     - `!mxc` - Show mxc of the attachment you replied to\n\
                     - `!whoami` - View your permission level\n\
-                    - `!sticker [mxc [body]]` - Send a sticker\n\
+                    - `!sticker [mxc [body] | name]` - Send a sticker, by default one of the configured ones\n\
                     - `!broken-sticker` - Send a sticker with empty url\n\
-                    - `!bridge-id [id]` - Set or clear a `m.bridge` state event with a given bridge_id";
+                    - `!bridge-id [id]` - Set or clear a `m.bridge` state event with a given bridge_id\n\
+                    - `!emote <shortcode> [--inline]` - Send an image from the room's or the bot's image packs as sticker or inline emoji";
 const TRUSTED_HELP: &str = "- `!spam [count [delay_seconds]]` - Send lots of text messasges\n\
+                            - `!format <case|all>` - Send formatted messages to test rendering, without case lists the available ones\n\
                             - `!stickerspam [count]` - Send lots of stickers\n\
-                            - `!image [width [height [info_width info_height]]]` - Send an image that you have never seen before\n\
+                            - `!image [width [height [info_width info_height]]]` - Send an image that you have never seen before, \
+                            options: `--format=png|jpeg|webp|avif|bmp|tiff|heic|svg`, `--quality=<1-100>`, `--orientation=<1-8>` (JPEG EXIF), \
+                            `--blurhash=real|mismatch|invalid|none`, `--thumbhash=real|mismatch|invalid|none`, \
+                            `--pattern=gradient|checkerboard|colorbars|alpha|grid|tall|wide`\n\
                             - `!imagemxc [width [height [info_width info_height]]]` - Like `!image` but send the mxc as notice only\n\
                             - `!imagespam [count [width [height]]]` - Like `!image` but more of that\n\
-                            - `!reactionspam [count]` - Spam (text) reactions\n\
+                            - `!reactionspam [count]` - Spam reactions to your command or the message you replied to, \
+                            options: `--emoji`, `--mxc[=<mxc>]` (custom emoji), `--long[=<length>]`, `--accounts` (react from the media account too)\n\
                             - `!thumb [width [height]]` - Like `!image` but with an added thumbnail\n\
-                            - `!tts <text>` - Send audio message for provided text using TTS\n\
+                            - `!tts <text>` - Send voice message for provided text using TTS, `--file` sends a plain WAV file instead, \
+                            options: `--voice=<name>`, `--speaker=<id>`, `--speed=<factor>`, `--voices` lists available voices. \
+                            Reply with `!tts` to read the replied-to message\n\
+                            - `!autoread [user|me|off]` - Read all messages by a user in this room aloud\n\
+                            - `!mediainfo` - Download the replied-to attachment and compare its actual size, mimetype, \
+                            dimensions and duration with what the event claims\n\
+                            - `!thumbprobe` - Request server-side thumbnails of the replied-to image at the standard sizes \
+                            with both `crop` and `scale`, `--grid` also posts the results as image\n\
+                            - `!transcribe` - Transcribe the replied-to voice or audio message, answering in a thread\n\
+                            - `!tone [frequency [seconds]]` - Send a sine tone, options: `--sweep=<to_frequency>`, `--stereo` (left/right channel test), \
+                            `--format=ogg|wav|mp3`, `--voice` (as voice message)\n\
+                            - `!noise [seconds]` - Send white noise, options: `--format=ogg|wav|mp3`, `--voice`\n\
+                            - `!silence [seconds]` - Send silence, options: `--format=ogg|wav|mp3`, `--voice`\n\
                             - `!audio <text>` - Send audio message for provided text using TTS\n\
                             - `!thread [count]` - Send lots of text messages in a thread\n\
                             - `!reply [count]` - Send lots of text messages as replies\n\
                             - `!invite [title]` - Create a new room and invite you to it\n\
                             - `!pingroom` - Ping the room\n\
-                            - `!typing [seconds]` - Send typing indicator";
+                            - `!typing [seconds]` - Send typing indicator\n\
+                            - `!redact [reason]` - Redact the replied-to bot message, or the bot's reactions, edits and thread replies to the replied-to message\n\
+                            - `!redactspam [count]` - Send lots of text messages and redact them right away\n\
+                            - `!poll [\"question\" [answers...]]` - Start a poll, options: `--undisclosed`, `--max=<selections>`, `--stable`\n\
+                            - `!pollspam [count]` - Start lots of polls\n\
+                            - `!vote [answer numbers...]` - Vote on the replied-to poll, using the media account if available\n\
+                            - `!pollend` - End the replied-to poll\n\
+                            - `!location [lat lon [description]]` - Send a location, options: `--asset=self|pin`\n\
+                            - `!livelocation [seconds [description]]` - Share a moving live location\n\
+                            - `!file [size [mimetype [filename]]]` - Send a generated file, options: `--info-size=<size>`, `--info-mimetype=<mimetype>`, `--long-name[=<length>]`, `--unicode-name`\n\
+                            - `!uploadlimit` - Show the upload size limit of the homeserver\n\
+                            - `!bigfile [MB]` - Upload a random file up to the upload limit, and one just above it, \
+                            reporting throughput and errors (VIP only)\n\
+                            - `!video [width [height [seconds [info_width info_height]]]]` - Send a generated video\n\
+                            - `!animated [frames [gif|apng|webp [width [height]]]]` - Send an animated image\n\
+                            - `!animatedsticker [frames [gif|apng|webp [width [height]]]]` - Like `!animated` but as sticker\n\
+                            - `!badmedia <kind>` - Send a deliberately broken image, kinds: `all`, `truncated`, `wrongmime`, `empty`, `bomb`, `badmxc`, `wrongthumb`, `badkey`, `badhash`, `badiv`\n\
+                            - `!render [options] <text>` - Render text to an image with line wrapping and font fallbacks, \
+                            options: `--sticker`, `--font=<name>`, `--size=<px>`, `--width=<px>`, `--fg=<color>`, `--bg=<color>`, `--format=...`\n\
+                            - `!qr <text>` - Send a QR code, or reply with `!qr` to encode the message or `!qr link` for its permalink\n\
+                            - `!pack create [name]` - Create an image pack in this room, or for the bot with `--user`, \
+                            options: `--emoticon`, `--sticker`\n\
+                            - `!pack add <shortcode> [pack]` - Add the image you replied to to an image pack, options: `--user`, `--emoticon`, `--sticker`\n\
+                            - `!pack remove <shortcode> [pack]` - Remove an image from an image pack, options: `--user`\n\
+                            \n\
+                            Identical generated images and audio are only uploaded once, \
+                            `!image`, `!thumb`, `!animated`, `!render`, `!file`, `!video`, `!qr`, `!thumbprobe --grid`, `!tts`, `!tone`, `!noise` and `!silence` accept `--fresh` to upload again anyway, \
+                            e.g. when a reused upload no longer loads";
 
 pub async fn handle_command(
     cmd: &String,
@@ -99,27 +190,55 @@ pub async fn handle_command(
         "id" => handle_event_id(event, room).await,
         "room" => handle_room_id(event, room).await,
         "roomid" => handle_room_id(event, room).await,
+        "mediainfo" => handle_media_info(event, room, context.config).await,
+        "thumbprobe" => handle_thumb_probe(args, event, room, context).await,
         "mxc" => handle_mxc(event, room).await,
-        "spam" => handle_spam(args, event, room, context.config).await,
-        "stickerspam" => handle_sticker_spam(args, event, room, context.config).await,
-        "sticker" => handle_sticker(args, event, room).await,
+        "spam" => handle_spam(args, event, room, context).await,
+        "stickerspam" => handle_sticker_spam(args, event, room, context).await,
+        "sticker" => handle_sticker(args, event, room, context).await,
         "image" => handle_image_spam_with_count(1, args, event, room, context, false, false).await,
         "imagemxc" => handle_image_spam_with_count(1, args, event, room, context, false, true).await,
         "thumb" => handle_image_spam_with_count(1, args, event, room, context, true, false).await,
         "thumbnail" => handle_image_spam_with_count(1, args, event, room, context, true, false).await,
         "imagespam" => handle_image_spam(args, event, room, context).await,
-        "reactionspam" => handle_reaction_spam(args, event, room, context.config).await,
-        "tts" => handle_tts(event, room, context).await,
-        "audio" => handle_tts(event, room, context).await,
-        "voice" => handle_tts(event, room, context).await,
+        "reactionspam" => handle_reaction_spam(args, event, room, context).await,
+        "tts" => handle_tts(args, event, room, context).await,
+        "audio" => handle_tts(args, event, room, context).await,
+        "voice" => handle_tts(args, event, room, context).await,
+        "autoread" => handle_auto_read_toggle(args, event, room, context).await,
+        "transcribe" => handle_transcribe(event, room, context).await,
+        "tone" => handle_synthetic_audio(SyntheticAudio::Tone, args, event, room, context).await,
+        "noise" => handle_synthetic_audio(SyntheticAudio::Noise, args, event, room, context).await,
+        "silence" => handle_synthetic_audio(SyntheticAudio::Silence, args, event, room, context).await,
         "thread" => handle_thread_spam(args, event, room, context.config).await,
         "reply" => handle_reply_spam(args, event, room, context).await,
         "replies" => handle_reply_spam(args, event, room, context).await,
         "typing" => handle_typing(args, event, room, context.config).await,
         "broken-sticker" => handle_sticker_broken(event, room).await,
         "whoami" => handle_whoami(event, room, context.config).await,
+        "format" => handle_format(args, event, room, context).await,
         "bridge-id" => handle_bride_id(args, event, room).await,
         "invite" => handle_invite(args, event, room, context).await,
+        "redact" => handle_redact(args, event, room, context.config).await,
+        "redactspam" => handle_redact_spam(args, event, room, context.config).await,
+        "poll" => handle_poll(args, event, room, context.config).await,
+        "pollspam" => handle_poll_spam(args, event, room, context.config).await,
+        "vote" => handle_poll_vote(args, event, room, context).await,
+        "pollvote" => handle_poll_vote(args, event, room, context).await,
+        "pollend" => handle_poll_end(event, room, context.config).await,
+        "location" => handle_location(args, event, room, context.config).await,
+        "livelocation" => handle_live_location(args, event, room, context.config).await,
+        "file" => handle_file(args, event, room, context).await,
+        "uploadlimit" => handle_upload_limit(event, room, context).await,
+        "bigfile" => handle_big_file(args, event, room, context).await,
+        "video" => handle_video(args, event, room, context).await,
+        "animated" => handle_animated(args, event, room, context, false).await,
+        "animatedsticker" => handle_animated(args, event, room, context, true).await,
+        "badmedia" => handle_bad_media(args, event, room, context).await,
+        "render" => handle_render(args, event, room, context).await,
+        "qr" => handle_qr(args, event, room, context).await,
+        "pack" => handle_pack(args, event, room, context.config).await,
+        "emote" => handle_emote(args, event, room).await,
         _ => debug!("Ignore unknown command \"{}\" by {} in {}", cmd, event.sender, room.room_id()),
     }
 }
@@ -276,19 +395,391 @@ async fn handle_mxc(command: OriginalSyncRoomMessageEvent, room: Room) {
     });
 }
 
+// What the event says about an attachment, to compare with what was actually uploaded
+#[derive(Default)]
+struct ClaimedMedia {
+    source: Option<MediaSource>,
+    mimetype: Option<String>,
+    size: Option<u64>,
+    width: Option<u64>,
+    height: Option<u64>,
+    duration: Option<Duration>,
+    thumbnail_source: Option<MediaSource>,
+    thumbnail_info: Option<ThumbnailInfo>,
+}
+
+impl ClaimedMedia {
+    fn from_image(source: MediaSource, info: Option<ImageInfo>) -> ClaimedMedia {
+        let info = info.unwrap_or_default();
+        ClaimedMedia {
+            source: Some(source),
+            mimetype: info.mimetype,
+            size: info.size.map(Into::into),
+            width: info.width.map(Into::into),
+            height: info.height.map(Into::into),
+            duration: None,
+            thumbnail_source: info.thumbnail_source,
+            thumbnail_info: info.thumbnail_info.map(|i| *i),
+        }
+    }
+}
+
+async fn fetch_claimed_media(room: &Room, event_id: &OwnedEventId) -> anyhow::Result<ClaimedMedia> {
+    let event = room.event(event_id, None).await?
+        .into_raw()
+        .deserialize()?
+        .into_full_event(room.room_id().into());
+    let claimed = match event {
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(MessageLikeEvent::Original(message))) => {
+            match message.content.msgtype {
+                MessageType::Image(content) => ClaimedMedia::from_image(content.source, content.info.map(|i| *i)),
+                MessageType::Video(content) => {
+                    let info = content.info.map(|i| *i).unwrap_or_default();
+                    ClaimedMedia {
+                        source: Some(content.source),
+                        mimetype: info.mimetype,
+                        size: info.size.map(Into::into),
+                        width: info.width.map(Into::into),
+                        height: info.height.map(Into::into),
+                        duration: info.duration,
+                        thumbnail_source: info.thumbnail_source,
+                        thumbnail_info: info.thumbnail_info.map(|i| *i),
+                    }
+                }
+                MessageType::Audio(content) => {
+                    let info = content.info.map(|i| *i).unwrap_or_default();
+                    ClaimedMedia {
+                        source: Some(content.source),
+                        mimetype: info.mimetype,
+                        size: info.size.map(Into::into),
+                        duration: info.duration,
+                        ..ClaimedMedia::default()
+                    }
+                }
+                MessageType::File(content) => {
+                    let info = content.info.map(|i| *i).unwrap_or_default();
+                    ClaimedMedia {
+                        source: Some(content.source),
+                        mimetype: info.mimetype,
+                        size: info.size.map(Into::into),
+                        thumbnail_source: info.thumbnail_source,
+                        thumbnail_info: info.thumbnail_info.map(|i| *i),
+                        ..ClaimedMedia::default()
+                    }
+                }
+                _ => anyhow::bail!("Replied-to message does not appear to be a known attachment type"),
+            }
+        }
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::Sticker(MessageLikeEvent::Original(sticker))) => {
+            let source = match sticker.content.source {
+                StickerMediaSource::Plain(uri) => MediaSource::Plain(uri),
+                StickerMediaSource::Encrypted(file) => MediaSource::Encrypted(file),
+                _ => anyhow::bail!("Unknown sticker source"),
+            };
+            ClaimedMedia::from_image(source, Some(sticker.content.info))
+        }
+        _ => anyhow::bail!("Replied-to message does not appear to be a known attachment type"),
+    };
+    Ok(claimed)
+}
+
+// "actual (claimed ...)" with a warning sign if they differ
+fn compare_claim<T: std::fmt::Display + PartialEq>(actual: Option<T>, claimed: Option<T>) -> String {
+    compare_claim_by(actual, claimed, |a, b| a == b)
+}
+
+fn compare_claim_by<T: std::fmt::Display>(actual: Option<T>, claimed: Option<T>, same: impl Fn(&T, &T) -> bool) -> String {
+    let actual_str = actual.as_ref().map(|a| format!("`{a}`")).unwrap_or_else(|| "unknown".to_string());
+    match claimed {
+        None => format!("{actual_str} (not claimed)"),
+        Some(claimed) if actual.as_ref().is_some_and(|a| same(a, &claimed)) => format!("{actual_str} ✅"),
+        Some(claimed) => format!("{actual_str} (claimed `{claimed}`) ⚠️"),
+    }
+}
+
+// Containers and decoders disagree a little on where audio ends, so don't warn about that
+const DURATION_TOLERANCE_MS: u128 = 50;
+
+fn describe_media(
+    source: &MediaSource,
+    actual: &media_info::MediaInfo,
+    claimed_mimetype: Option<&str>,
+    claimed_size: Option<u64>,
+    claimed_dimensions: Option<(u64, u64)>,
+    claimed_duration: Option<Duration>,
+) -> String {
+    let (mxc, encrypted) = match source {
+        MediaSource::Plain(uri) => (uri, false),
+        MediaSource::Encrypted(file) => (&file.url, true),
+    };
+    let mut lines = vec![
+        format!("- mxc: `{mxc}`{}", if encrypted { " (encrypted)" } else { "" }),
+        format!("- Size: {}", compare_claim(Some(actual.size as u64), claimed_size)),
+        format!("- Mimetype: {}", compare_claim(actual.mimetype, claimed_mimetype)),
+    ];
+    let dimensions = actual.width.zip(actual.height).map(|(w, h)| format!("{w}x{h}"));
+    if dimensions.is_some() || claimed_dimensions.is_some() {
+        let claimed_dimensions = claimed_dimensions.map(|(w, h)| format!("{w}x{h}"));
+        lines.push(format!("- Dimensions: {}", compare_claim(dimensions, claimed_dimensions)));
+    }
+    if actual.duration.is_some() || claimed_duration.is_some() {
+        lines.push(format!(
+            "- Duration (ms): {}",
+            compare_claim_by(
+                actual.duration.map(|d| d.as_millis()),
+                claimed_duration.map(|d| d.as_millis()),
+                |a, b| a.abs_diff(*b) <= DURATION_TOLERANCE_MS,
+            ),
+        ));
+    }
+    lines.push(format!("- SHA-256: `{}`", actual.sha256));
+    lines.join("\n")
+}
+
+async fn download_and_probe(client: &Client, source: &MediaSource) -> anyhow::Result<media_info::MediaInfo> {
+    let request = MediaRequestParameters { source: source.clone(), format: MediaFormat::File };
+    let data = client.media().get_media_content(&request, false).await?;
+    Ok(tokio::task::spawn_blocking(move || media_info::probe(&data)).await?)
+}
+
+async fn handle_media_info(command: OriginalSyncRoomMessageEvent, room: Room, config: Config) {
+    let trusted = is_user_trusted(&command.sender, config);
+    debug!("Got !mediainfo in {} from {}, trusted={trusted}", room.room_id(), command.sender);
+    if !trusted {
+        return;
+    }
+    let event_id = if let Some(Relation::Reply { in_reply_to }) = command.content.relates_to {
+        in_reply_to.event_id
+    } else {
+        let content =
+            RoomMessageEventContent::notice_plain("Please reply to an attachment message while issuing the !mediainfo command");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send error message in {}: {}", room.room_id(), e);
+        }
+        return;
+    };
+    tokio::spawn(async move {
+        let msg = match media_info_report(&room, &event_id).await {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!("Failed to get media info for {} in {}: {}", event_id, room.room_id(), e);
+                format!("Failed to get media info: {e}")
+            }
+        };
+        let content = assign!(RoomMessageEventContent::notice_markdown(msg), {
+            relates_to: Some(Relation::Reply { in_reply_to: InReplyTo::new(event_id) }),
+        });
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send media info in {}: {}", room.room_id(), e);
+        }
+    });
+}
+
+async fn media_info_report(room: &Room, event_id: &OwnedEventId) -> anyhow::Result<String> {
+    let claimed = fetch_claimed_media(room, event_id).await?;
+    let source = claimed.source.ok_or(anyhow::anyhow!("No media source"))?;
+    let client = room.client();
+
+    let actual = download_and_probe(&client, &source).await?;
+    let mut msg = format!(
+        "**Attachment**\n{}",
+        describe_media(
+            &source,
+            &actual,
+            claimed.mimetype.as_deref(),
+            claimed.size,
+            claimed.width.zip(claimed.height),
+            claimed.duration,
+        ),
+    );
+
+    msg.push_str("\n\n**Thumbnail**\n");
+    match (claimed.thumbnail_source, claimed.thumbnail_info) {
+        (Some(thumbnail_source), thumbnail_info) => {
+            let thumbnail_info = thumbnail_info.unwrap_or_default();
+            match download_and_probe(&client, &thumbnail_source).await {
+                Ok(thumbnail) => msg.push_str(&describe_media(
+                    &thumbnail_source,
+                    &thumbnail,
+                    thumbnail_info.mimetype.as_deref(),
+                    thumbnail_info.size.map(Into::into),
+                    thumbnail_info.width.map(u64::from).zip(thumbnail_info.height.map(u64::from)),
+                    None,
+                )),
+                Err(e) => msg.push_str(&format!("Failed to download: {e}")),
+            }
+        }
+        (None, Some(_)) => msg.push_str("None, but thumbnail info is set ⚠️"),
+        (None, None) => msg.push_str("None"),
+    }
+    Ok(msg)
+}
+
+// Thumbnail sizes servers should pre-generate, as recommended by the spec
+const THUMBNAIL_PROBE_SIZES: &[(u32, u32)] = &[(32, 32), (96, 96), (320, 240), (640, 480), (800, 600)];
+
+struct ThumbnailProbe {
+    label: String,
+    result: anyhow::Result<(Option<String>, Vec<u8>)>,
+    elapsed: Duration,
+}
+
+async fn handle_thumb_probe(
+    args: SplitWhitespace<'_>,
+    command: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let trusted = is_user_trusted(&command.sender, context.config.clone());
+    debug!("Got !thumbprobe in {} from {}, trusted={trusted}", room.room_id(), command.sender);
+    if !trusted {
+        return;
+    }
+    let event_id = if let Some(Relation::Reply { in_reply_to }) = command.content.relates_to {
+        in_reply_to.event_id
+    } else {
+        let content =
+            RoomMessageEventContent::notice_plain("Please reply to an image while issuing the !thumbprobe command");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send error message in {}: {}", room.room_id(), e);
+        }
+        return;
+    };
+    let (options, _) = args.partition::<Vec<_>, _>(|a| a.starts_with("--"));
+    let post_grid = options.contains(&"--grid");
+    let fresh = options.contains(&"--fresh");
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache;
+
+    tokio::spawn(async move {
+        let mxc = match fetch_claimed_media(&room, &event_id).await.and_then(|claimed| match claimed.source {
+            Some(MediaSource::Plain(mxc)) => Ok(mxc),
+            Some(MediaSource::Encrypted(_)) => Err(anyhow::anyhow!("Servers can not thumbnail encrypted media")),
+            None => Err(anyhow::anyhow!("No media source")),
+        }) {
+            Ok(mxc) => mxc,
+            Err(e) => {
+                let content = RoomMessageEventContent::notice_plain(format!("Can not probe thumbnails: {e}"));
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send thumbprobe error message in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+        };
+
+        let mut probes = Vec::new();
+        for (width, height) in THUMBNAIL_PROBE_SIZES {
+            for method in [Method::Crop, Method::Scale] {
+                let label = format!("{width}x{height} {}", method.as_str());
+                let started = std::time::Instant::now();
+                let result = fetch_thumbnail(&room.client(), &mxc, *width, *height, method).await;
+                probes.push(ThumbnailProbe { label, result, elapsed: started.elapsed() });
+            }
+        }
+
+        let mut msg = format!("Thumbnails for `{mxc}`\n\n| Requested | Result | Mimetype | Size | Time |\n|---|---|---|---|---|\n");
+        let mut grid_images = Vec::new();
+        for probe in probes {
+            let probed = match probe.result {
+                Ok((content_type, data)) => tokio::task::spawn_blocking(move || {
+                    let info = media_info::probe(&data);
+                    (content_type, data, info)
+                }).await.map_err(anyhow::Error::from),
+                Err(e) => Err(e),
+            };
+            let row = match probed {
+                Ok((content_type, data, info)) => {
+                    let dimensions = info.width.zip(info.height)
+                        .map(|(w, h)| format!("{w}x{h}"))
+                        .unwrap_or_else(|| "unreadable".to_string());
+                    let mimetype = match (content_type.as_deref(), info.mimetype) {
+                        (Some(claimed), Some(sniffed)) if claimed != sniffed => {
+                            format!("{} (actually {sniffed}) ⚠️", escape_table_cell(claimed))
+                        }
+                        (Some(claimed), _) => escape_table_cell(claimed),
+                        (None, sniffed) => format!("none (actually {}) ⚠️", sniffed.unwrap_or("unknown")),
+                    };
+                    let row = format!("{dimensions} | {mimetype} | {} bytes", data.len());
+                    grid_images.push((format!("{} → {dimensions}", probe.label), data));
+                    row
+                }
+                Err(e) => format!("{} | | ", escape_table_cell(&e.to_string())),
+            };
+            msg.push_str(&format!("| {} | {row} | {}ms |\n", probe.label, probe.elapsed.as_millis()));
+        }
+        let content = assign!(RoomMessageEventContent::notice_markdown(msg), {
+            relates_to: Some(Relation::Reply { in_reply_to: InReplyTo::new(event_id) }),
+        });
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send thumbprobe results in {}: {}", room.room_id(), e);
+        }
+
+        if post_grid && !grid_images.is_empty() {
+            let target = UploadTarget { media_client: &media_client, upload_cache: &upload_cache, fresh };
+            if let Err(e) = send_thumbnail_grid(&room, &target, &grid_images).await {
+                warn!("Failed to send thumbnail grid in {}: {}", room.room_id(), e);
+            }
+        }
+    });
+}
+
+// Server-generated thumbnail via the authenticated media endpoint, with the returned content type
+async fn fetch_thumbnail(
+    client: &Client,
+    mxc: &OwnedMxcUri,
+    width: u32,
+    height: u32,
+    method: Method,
+) -> anyhow::Result<(Option<String>, Vec<u8>)> {
+    let mut request = get_content_thumbnail::v1::Request::from_uri(mxc, width.into(), height.into())?;
+    request.method = Some(method);
+    let response = client.send(request).await?;
+    Ok((response.content_type, response.file))
+}
+
+// Server errors and content types end up in a markdown table, keep them on one line in one cell
+fn escape_table_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+async fn send_thumbnail_grid(room: &Room, target: &UploadTarget<'_>, images: &[(String, Vec<u8>)]) -> anyhow::Result<()> {
+    let encoding = ImageEncoding::default();
+    let (image, width, height) = image_generator::create_image_grid(images, 2, &encoding).await?;
+    let image_size = image.len();
+    let image_mime: mime::Mime = encoding.format.mimetype().parse().expect("Valid mimetype");
+    let image_uri = target.upload(image_mime.essence_str(), image).await?;
+
+    let image_info = assign!(ImageInfo::new(), {
+        width: width.try_into().ok(),
+        height: height.try_into().ok(),
+        size: image_size.try_into().ok(),
+        mimetype: Some(image_mime.essence_str().to_string()),
+    });
+    let image_content = ImageMessageEventContent::plain(
+        format!("thumbnails.{}", encoding.format.extension()),
+        image_uri.clone(),
+    ).info(Some(Box::new(image_info)));
+    if let Err(e) = room.send(RoomMessageEventContent::new(MessageType::Image(image_content))).await {
+        target.upload_cache.forget(&image_uri).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
 async fn handle_spam(
     mut args: SplitWhitespace<'_>,
     event: OriginalSyncRoomMessageEvent,
     room: Room,
-    config: Config
+    context: WipContext,
 ) {
+    let config = context.config.clone();
     let vip = is_user_vip(&event.sender, config.clone());
     let trusted = is_user_trusted_not_vip(&event.sender, config.clone());
     debug!("Got !spam in {} from {}, vip={vip}, trusted={trusted}", room.room_id(), event.sender);
     let max_spam_count = if room.is_public().unwrap_or(true) {
         // No spam in public rooms please...
         // But showing a single spam sticker wouldn't hurt?
-        handle_sticker_spam(args, event, room, config).await;
+        handle_sticker_spam(args, event, room, context).await;
         return;
     } else if vip {
         config.get::<usize>("bot.text_spam.vip_limit").unwrap_or(500)
@@ -433,12 +924,16 @@ async fn handle_reply_spam(
     });
 }
 
+// Like the built-in sticker list this used to cycle through once
+const STICKER_SPAM_DEFAULT_COUNT: usize = 3;
+
 async fn handle_sticker_spam(
     mut args: SplitWhitespace<'_>,
     event: OriginalSyncRoomMessageEvent,
     room: Room,
-    config: Config
+    context: WipContext,
 ) {
+    let config = context.config;
     let vip = is_user_vip(&event.sender, config.clone());
     let trusted = is_user_trusted_not_vip(&event.sender, config.clone());
     debug!("Got !stickerspam in {} from {}, vip={vip}, trusted={trusted}", room.room_id(), event.sender);
@@ -452,22 +947,22 @@ async fn handle_sticker_spam(
         1
     };
     let desired_count = args.next().unwrap_or_default().parse::<usize>();
-    let count = cmp::min(desired_count.unwrap_or(STICKER_SPAM.len()), max_spam_count);
+    let stickers = context.stickers;
+    let count = cmp::min(desired_count.unwrap_or(STICKER_SPAM_DEFAULT_COUNT), max_spam_count);
     tokio::spawn(async move {
         for i in 0..count {
-            let spam_select = STICKER_SPAM[i % STICKER_SPAM.len()];
-            let text_spam_select = TEXT_SPAM[i % TEXT_SPAM.len()];
+            let sticker = &stickers[i % stickers.len()];
             let content = StickerEventContent::new(
-                text_spam_select.to_string(), // body
-                ImageInfo::new(),
-                spam_select.into(), // mxc
+                sticker.body.clone(),
+                sticker.image_info(),
+                sticker.mxc.clone(),
             );
             if let Err(e) = room.send(content).await {
                 warn!("Failed to stickerspam in {}: {}", room.room_id(), e);
                 return
             }
         }
-        if count > STICKER_SPAM.len() {
+        if count > stickers.len() {
             let content = RoomMessageEventContent::notice_plain("Done!");
             if let Err(e) = room.send(content).await {
                 warn!("Failed to stickerspam in {}: {}", room.room_id(), e);
@@ -480,15 +975,27 @@ async fn handle_sticker(
     mut args: SplitWhitespace<'_>,
     event: OriginalSyncRoomMessageEvent,
     room: Room,
+    context: WipContext,
 ) {
     debug!("Got !sticker in {} from {}", room.room_id(), event.sender);
-    let mxc = args.next().unwrap_or("mxc://spiritcroc.de/mkJFKqrNzBGBcILPTIPlTPOV");
-    let body = args.next().unwrap_or("Sticker");
-    let content = StickerEventContent::new(
-        body.to_string(),
-        ImageInfo::new(),
-        mxc.into(),
-    );
+    let content = match args.next() {
+        Some(mxc) if mxc.starts_with("mxc://") => StickerEventContent::new(
+            args.next().unwrap_or("Sticker").to_string(),
+            ImageInfo::new(),
+            mxc.into(),
+        ),
+        name => {
+            // Configured sticker by name, or the first one
+            let sticker = name
+                .and_then(|name| context.stickers.iter().find(|s| s.body.eq_ignore_ascii_case(name)))
+                .unwrap_or(&context.stickers[0]);
+            StickerEventContent::new(
+                sticker.body.clone(),
+                sticker.image_info(),
+                sticker.mxc.clone(),
+            )
+        }
+    };
     if let Err(e) = room.send(content).await {
         warn!("Failed to stickerspam in {}: {}", room.room_id(), e);
         return
@@ -512,24 +1019,57 @@ async fn handle_sticker_broken(
 }
 
 async fn handle_image_spam(
-    mut args: SplitWhitespace<'_>,
+    args: SplitWhitespace<'_>,
     event: OriginalSyncRoomMessageEvent,
     room: Room,
     context: WipContext,
 ) {
+    let (options, args): (Vec<&str>, Vec<&str>) = args.partition(|a| a.starts_with("--"));
+    let mut args = args.into_iter();
     let desired_count = args.next().unwrap_or_default().parse::<usize>().unwrap_or(3);
-    handle_image_spam_with_count(desired_count, args, event, room, context, false, false).await;
+    handle_image_spam_with_count(desired_count, args.chain(options), event, room, context, false, false).await;
+}
+
+fn parse_image_encoding(options: &[&str]) -> ImageEncoding {
+    let mut encoding = ImageEncoding::default();
+    for option in options {
+        if let Some(format) = option.strip_prefix("--format=").and_then(ImageFormat::parse) {
+            encoding.format = format;
+        } else if let Some(quality) = option.strip_prefix("--quality=") {
+            encoding.quality = quality.parse::<usize>().ok().map(|q| q.clamp(1, 100));
+        } else if let Some(orientation) = option.strip_prefix("--orientation=") {
+            encoding.orientation = orientation.parse::<u16>().ok().filter(|o| (1..=8).contains(o));
+        }
+    }
+    // Orientation is only written for JPEG, so default to that if it is requested
+    if encoding.orientation.is_some() && !options.iter().any(|o| o.starts_with("--format=")) {
+        encoding.format = ImageFormat::Jpeg;
+    }
+    encoding
 }
 
-async fn handle_image_spam_with_count(
+async fn handle_image_spam_with_count<'a>(
     desired_count: usize,
-    mut args: SplitWhitespace<'_>,
+    args: impl Iterator<Item = &'a str>,
     event: OriginalSyncRoomMessageEvent,
     room: Room,
     context: WipContext,
     with_thumbnail: bool,
     only_notice: bool,
 ) {
+    let (options, args): (Vec<&str>, Vec<&str>) = args.partition(|a| a.starts_with("--"));
+    let mut args = args.into_iter();
+    let encoding = parse_image_encoding(&options);
+    let placeholder_mode = |key: &str| options.iter()
+        .find_map(|o| o.strip_prefix(key))
+        .and_then(PlaceholderMode::parse)
+        .unwrap_or(PlaceholderMode::Real);
+    let blurhash_mode = placeholder_mode("--blurhash=");
+    let thumbhash_mode = placeholder_mode("--thumbhash=");
+    let pattern = options.iter()
+        .find_map(|o| o.strip_prefix("--pattern="))
+        .and_then(ImagePattern::parse);
+    let fresh = options.contains(&"--fresh");
     let config = context.config;
     let vip = is_user_vip(&event.sender, config.clone());
     let trusted = is_user_trusted_not_vip(&event.sender, config.clone());
@@ -545,71 +1085,119 @@ async fn handle_image_spam_with_count(
     };
     let max_size = config.get::<usize>("bot.image_spam.max_size").unwrap_or(500);
     let count = cmp::min(desired_count, max_spam_count);
-    let width = cmp::min(args.next().unwrap_or_default().parse::<usize>().unwrap_or(150), max_size);
-    let height = cmp::min(args.next().unwrap_or_default().parse::<usize>().unwrap_or(width), max_size);
+    let (default_width, default_height) = pattern
+        .and_then(|p| p.default_size(max_size))
+        .map(|(w, h)| (w, Some(h)))
+        .unwrap_or((150, None));
+    let width = cmp::max(cmp::min(args.next().unwrap_or_default().parse::<usize>().unwrap_or(default_width), max_size), 1);
+    let height = cmp::max(cmp::min(args.next().unwrap_or_default().parse::<usize>().unwrap_or(default_height.unwrap_or(width)), max_size), 1);
     let claimed_width = args.next().unwrap_or_default().parse::<usize>().unwrap_or(width);
     let claimed_height = args.next().unwrap_or_default().parse::<usize>().unwrap_or(height);
     let text_override = args.next().map(|t| t.to_string());
-    let font_size = (if count == 1 { 42.0 } else { 64.0 }) * ((width as f64)/150.0);
+    let font_size = (if count == 1 { 42.0 } else { 64.0 }) * ((cmp::min(width, height) as f64)/150.0);
 
     let media_client = context.media_client.unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache;
+    let image_mime: mime::Mime = encoding.format.mimetype().parse().expect("Valid mimetype");
 
     tokio::spawn(async move {
         for i in 1..=count {
             let text = if count == 1 { text_override.clone() } else { Some(i.to_string()) };
             let background_color: u32 = rand::random();
             let background_color = format!("#{:06x}", background_color % 0xffffff);
-            let image = image_generator::create_text_image(
-                text.as_ref(),
-                &background_color,
-                "#ffffff",
-                width,
-                height,
-                font_size
-            ).await;
-            let image = match image {
-                Ok(i) => i,
-                Err(e) => {
-                    error!("Failed to generate image: {}", e);
-                    return;
+            let (image, pixels, pattern_thumbnail) = if let Some(pattern) = pattern {
+                let text = text.clone();
+                let encoding = encoding.clone();
+                let rendered = tokio::task::spawn_blocking(move || image_generator::create_pattern_image(
+                    pattern,
+                    text.as_ref(),
+                    width,
+                    height,
+                    font_size,
+                    &encoding,
+                    with_thumbnail,
+                )).await;
+                match rendered {
+                    Ok(Ok(rendered)) => (rendered.image, Ok(rendered.pixels), rendered.thumbnail),
+                    Ok(Err(e)) => {
+                        error!("Failed to generate image: {}", e);
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Failed to generate image: {}", e);
+                        return;
+                    }
                 }
+            } else {
+                let image = match image_generator::create_text_image(
+                    text.as_ref(),
+                    &background_color,
+                    "#ffffff",
+                    width,
+                    height,
+                    font_size,
+                    &encoding,
+                ).await {
+                    Ok(i) => i,
+                    Err(e) => {
+                        error!("Failed to generate image: {}", e);
+                        return;
+                    }
+                };
+                let pixels = image_generator::create_text_image_rgba(text.as_ref(), &background_color, "#ffffff", width, height, font_size);
+                (image, pixels, None)
             };
             let image_size = image.len();
 
+            let (blurhash, thumbhash) = match pixels {
+                Ok(pixels) => (
+                    blurhash::blurhash_for(blurhash_mode, &pixels, width, height),
+                    blurhash::thumbhash_for(thumbhash_mode, &pixels, width, height).map(Base64::new),
+                ),
+                Err(e) => {
+                    error!("Failed to generate pixels for placeholder hashes: {}", e);
+                    (None, None)
+                }
+            };
+
             let (thumbnail_info, thumbnail_uri) = if with_thumbnail {
-                let thumbnail_text = format!("t.{}", text.unwrap_or_default());
-                let thumb_width = width/2;
-                let thumb_height = height/2;
-                let thumb_font_size = font_size/2.0;
-                match image_generator::create_text_image(
-                    Some(&thumbnail_text),
-                    &background_color,
-                    "#ffffff",
-                    thumb_width,
-                    thumb_height,
-                    thumb_font_size
-                ).await {
+                // Patterns come with a scaled down copy, text images get a separate smaller rendering
+                let thumbnail = match pattern_thumbnail {
+                    Some(thumbnail) => Ok(thumbnail),
+                    None => {
+                        let thumbnail_text = format!("t.{}", text.unwrap_or_default());
+                        let thumb_width = width/2;
+                        let thumb_height = height/2;
+                        let thumb_font_size = font_size/2.0;
+                        image_generator::create_text_image(
+                            Some(&thumbnail_text),
+                            &background_color,
+                            "#ffffff",
+                            thumb_width,
+                            thumb_height,
+                            thumb_font_size,
+                            &encoding,
+                        ).await.map(|thumb_image| (thumb_image, thumb_width, thumb_height))
+                    }
+                };
+                match thumbnail {
                     Err(e) => {
                         error!("Failed to generate thumbnail image: {}", e);
                         (None, None)
                     }
-                    Ok(thumb_image) => {
+                    Ok((thumb_image, thumb_width, thumb_height)) => {
                         let thumbnail_info = assign!(ThumbnailInfo::new(), {
                             width: thumb_width.try_into().ok(),
                             height: thumb_height.try_into().ok(),
                             size: thumb_image.len().try_into().ok(),
-                            mimetype: Some(mime::IMAGE_PNG.to_string()),
+                            mimetype: Some(image_mime.to_string()),
                         });
 
-                        match media_client.media().upload(
-                            &mime::IMAGE_PNG,
-                            thumb_image,
-                            None,
-                        ).await {
-                            Ok(u) => {
+                        match upload_cache.upload(&media_client, &image_mime, thumb_image, fresh).await {
+                            Ok(mxc) => {
                                 (
                                     Some(Box::new(ThumbnailInfo::from(thumbnail_info))),
-                                    Some(u.content_uri)
+                                    Some(mxc)
                                 )
                             },
                             Err(e) => {
@@ -625,24 +1213,20 @@ async fn handle_image_spam_with_count(
 
             let thumbnail_source = thumbnail_uri.clone().map(MediaSource::Plain);
 
+            // The displayed size, even when the EXIF orientation transposes the stored pixels
             let image_info = assign!(ImageInfo::new(), {
                 width: claimed_width.try_into().ok(),
                 height: claimed_height.try_into().ok(),
                 size: image_size.try_into().ok(),
-                // TODO: random blurhash? (random is better for testing than a solid color like the
-                //  generated images) - https://github.com/woltapp/blurhash/blob/master/Algorithm.md
-                blurhash: Some("LEDuYo=b9]tP02xt}?jGEj9]4;of".to_string()),
-                mimetype: Some(mime::IMAGE_PNG.essence_str().to_string()),
+                blurhash: blurhash,
+                thumbhash: thumbhash,
+                mimetype: Some(image_mime.essence_str().to_string()),
                 thumbnail_info: thumbnail_info,
                 thumbnail_source: thumbnail_source,
             });
 
-            let image_upload = match media_client.media().upload(
-                &mime::IMAGE_PNG,
-                image,
-                None,
-            ).await {
-                Ok(u) => u,
+            let image_uri = match upload_cache.upload(&media_client, &image_mime, image, fresh).await {
+                Ok(mxc) => mxc,
                 Err(e) => {
                     error!("Failed to upload image: {}", e);
                     return
@@ -650,12 +1234,12 @@ async fn handle_image_spam_with_count(
             };
 
             let message = if only_notice {
-                let msg_html = format!("<pre><code>{}</code></pre>", image_upload.content_uri);
-                RoomMessageEventContent::notice_html(image_upload.content_uri.clone(), msg_html)
+                let msg_html = format!("<pre><code>{}</code></pre>", image_uri);
+                RoomMessageEventContent::notice_html(image_uri.clone(), msg_html)
             } else {
                 let image_content = ImageMessageEventContent::plain(
-                    format!("{i}.png"),
-                    image_upload.content_uri.clone(),
+                    format!("{i}.{}", encoding.format.extension()),
+                    image_uri.clone(),
                 ).info(Some(Box::new(image_info)));
 
                 RoomMessageEventContent::new(
@@ -665,20 +1249,25 @@ async fn handle_image_spam_with_count(
 
             if let Err(e) = room.send(message).await {
                 warn!("Failed to send image in {}: {}", room.room_id(), e);
+                upload_cache.forget(&image_uri).await;
+                if let Some(thumbnail_uri) = &thumbnail_uri {
+                    upload_cache.forget(thumbnail_uri).await;
+                }
                 return;
             }
 
-            trace!("Successfully sent image with size {image_size}, mxc {} and thumbnail {:?}", image_upload.content_uri, thumbnail_uri);
+            trace!("Successfully sent image with size {image_size}, mxc {} and thumbnail {:?}", image_uri, thumbnail_uri);
         }
     });
 }
 
 async fn handle_reaction_spam(
-    mut args: SplitWhitespace<'_>,
+    args: SplitWhitespace<'_>,
     event: OriginalSyncRoomMessageEvent,
     room: Room,
-    config: Config
+    context: WipContext,
 ) {
+    let config = context.config;
     let vip = is_user_vip(&event.sender, config.clone());
     let trusted = is_user_trusted_not_vip(&event.sender, config.clone());
     debug!("Got !reaction_spam in {} from {}, vip={vip}, trusted={trusted}", room.room_id(), event.sender);
@@ -691,124 +1280,2267 @@ async fn handle_reaction_spam(
     } else {
         1
     };
-    let desired_count = args.next().unwrap_or_default().parse::<usize>();
-    let count = cmp::min(desired_count.unwrap_or(STICKER_SPAM.len()), max_spam_count);
+    let (options, args): (Vec<&str>, Vec<&str>) = args.partition(|a| a.starts_with("--"));
+    let desired_count = args.first().copied().unwrap_or_default().parse::<usize>();
+    let count = cmp::min(desired_count.unwrap_or(3), max_spam_count);
+
+    // Custom emoji reactions use the mxc as key, with a shortcode for clients to show as text
+    let custom_emoji: Option<Vec<(String, String)>> = options.iter().find_map(|o| {
+        if let Some(mxc) = o.strip_prefix("--mxc=") {
+            Some(vec![(mxc.to_string(), "custom".to_string())])
+        } else if *o == "--mxc" {
+            Some(context.stickers.iter().map(|s| (s.mxc.to_string(), s.body.clone())).collect())
+        } else {
+            None
+        }
+    });
+    let emoji = options.contains(&"--emoji");
+    let long_length = options.iter().find_map(|o| {
+        if *o == "--long" {
+            Some(1000)
+        } else {
+            o.strip_prefix("--long=").and_then(|l| l.parse::<usize>().ok())
+        }
+    });
+
+    let target = if let Some(Relation::Reply { in_reply_to }) = &event.content.relates_to {
+        in_reply_to.event_id.clone()
+    } else {
+        event.event_id.clone()
+    };
+
+    // Reacting with the same keys from several accounts adds up in the aggregation counts
+    let mut reacting_rooms = vec![room.clone()];
+    if options.contains(&"--accounts") {
+        if let Some(media_room) = context.media_client.and_then(|client| client.get_room(room.room_id())) {
+            reacting_rooms.push(media_room);
+        }
+    }
+
     tokio::spawn(async move {
         for i in 0..count {
-            let reaction = if count == 1 {
-                "🐢".to_string()
+            let (reaction, shortcode) = if let Some(custom_emoji) = &custom_emoji {
+                let (mxc, name) = &custom_emoji[i % custom_emoji.len()];
+                (mxc.clone(), Some(format!(":{name}:")))
+            } else if emoji {
+                (REACTION_EMOJI[i % REACTION_EMOJI.len()].to_string(), None)
+            } else if let Some(length) = long_length {
+                let prefix = format!("{} ", i+1);
+                let reaction: String = prefix.chars().chain(TEXT_SPAM.join(" ").chars().cycle()).take(length).collect();
+                (reaction, None)
+            } else if count == 1 {
+                ("🐢".to_string(), None)
             } else {
-                (i+1).to_string()
+                ((i+1).to_string(), None)
             };
-            let content = ReactionEventContent::new(
-                Annotation::new(
-                    event.event_id.clone(),
-                    reaction,
-                )
-            );
-            if let Err(e) = room.send(content).await {
-                warn!("Failed to reactionspam in {}: {}", room.room_id(), e);
-                return
+            for reacting_room in &reacting_rooms {
+                let result = if let Some(shortcode) = &shortcode {
+                    let content = serde_json::json!({
+                        "m.relates_to": {
+                            "rel_type": "m.annotation",
+                            "event_id": target,
+                            "key": reaction,
+                        },
+                        "com.beeper.reaction.shortcode": shortcode,
+                    });
+                    reacting_room.send_raw("m.reaction", content).await.map(|_| ())
+                } else {
+                    let content = ReactionEventContent::new(
+                        Annotation::new(
+                            target.clone(),
+                            reaction.clone(),
+                        )
+                    );
+                    reacting_room.send(content).await.map(|_| ())
+                };
+                if let Err(e) = result {
+                    warn!("Failed to reactionspam in {}: {}", room.room_id(), e);
+                    return
+                }
             }
         }
     });
 }
 
-
-async fn handle_tts(
+async fn handle_redact(
+    args: SplitWhitespace<'_>,
     event: OriginalSyncRoomMessageEvent,
     room: Room,
-    context: WipContext,
+    config: Config,
 ) {
-    let config = context.config;
-    let vip = is_user_vip(&event.sender, config.clone());
-    let trusted = is_user_trusted_not_vip(&event.sender, config.clone());
-    debug!("Got !tts in {} from {}, vip={vip}, trusted={trusted}", room.room_id(), event.sender);
-    if !vip && !trusted {
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
         return;
     }
-
-    let text = event.content.body().split_once(char::is_whitespace).map(|p| p.1).unwrap_or("Nope").to_string();
-
+    let reason = Some(command_text(event.content.body(), &args).to_string()).filter(|r| !r.is_empty());
+    let event_id = if let Some(Relation::Reply { in_reply_to }) = event.content.relates_to {
+        in_reply_to.event_id
+    } else {
+        debug!("Got !redact without reply in {} from {}", room.room_id(), event.sender);
+        let content =
+            RoomMessageEventContent::notice_plain("Please reply to a message while issuing the !redact command");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send error message in {}: {}", room.room_id(), e);
+        }
+        return;
+    };
+    debug!("Got !redact in {} from {}", room.room_id(), event.sender);
     tokio::spawn(async move {
-        let config_path = match config.get::<String>("tts.config_path").map(PathBuf::from) {
-            Ok(path) => path,
-            Err(e) => {
-                error!("No TTS config path provided: {e}");
-                return;
-            }
-        };
-        let model = match piper_rs::from_config_path(config_path.as_path()) {
-            Ok(model) => model,
+        let sender = match room.event(&event_id, None).await {
+            Ok(event) => event.into_raw().deserialize().map(|event| event.sender().to_owned()).ok(),
             Err(e) => {
-                error!("Failed to TTS: {e}");
-                return;
+                warn!("Failed to look up replied-to event {} for redaction in {}: {}", event_id, room.room_id(), e);
+                None
             }
         };
-        let synth = match PiperSpeechSynthesizer::new(model) {
-            Ok(synth) => synth,
-            Err(e) => {
-                error!("Failed to TTS: {e}");
-                return;
+        let Some(sender) = sender else {
+            let content =
+                RoomMessageEventContent::notice_plain("Failed to look-up replied-to event");
+            if let Err(e) = room.send(content).await {
+                warn!("Failed to send redaction error message in {}: {}", room.room_id(), e);
             }
+            return;
         };
 
-        // TODO figure out to do completely in memory
-        let file = match NamedTempFile::new() {
-            Ok(file) => file,
-            Err(e) => {
-                error!("Failed to create TTS tempfile: {e}");
-                return;
+        // Redact our own message directly, otherwise clean up what we related to it
+        let to_redact = if sender == room.own_user_id() {
+            vec!(event_id)
+        } else {
+            match find_own_relations(&room, event_id.clone()).await {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Failed to look up relations of {} in {}: {}", event_id, room.room_id(), e);
+                    let content =
+                        RoomMessageEventContent::notice_plain("Failed to look-up related events");
+                    if let Err(e) = room.send(content).await {
+                        warn!("Failed to send redaction error message in {}: {}", room.room_id(), e);
+                    }
+                    return;
+                }
             }
         };
 
-        debug!("TTS initialized");
-
-        if let Err(e) = synth.synthesize_to_file(file.path(), text, None) {
-            error!("Failed to synthesize TTS: {e}");
-            return
+        if to_redact.is_empty() {
+            let content =
+                RoomMessageEventContent::notice_plain("Nothing of mine to redact here");
+            if let Err(e) = room.send(content).await {
+                warn!("Failed to send redaction notice in {}: {}", room.room_id(), e);
+            }
+            return;
         }
 
-        debug!("TTS generated");
-
-        let wav_content = match std::fs::read(file.path()) {
-            Ok(c) => c,
-            Err(e) => {
-                error!("Failed to read TTS tempfile: {e}");
+        for event_id in to_redact {
+            if let Err(e) = room.redact(&event_id, reason.as_deref(), None).await {
+                warn!("Failed to redact {} in {}: {}", event_id, room.room_id(), e);
                 return;
             }
-        };
-
-        debug!("TTS written");
+        }
+    });
+}
 
-        let media_client = context.media_client.unwrap_or_else(|| room.client());
-        let upload = match media_client.media().upload(
-            &mime::APPLICATION_OCTET_STREAM,
-            wav_content,
-            None,
-        ).await {
-            Ok(u) => u,
-            Err(e) => {
-                error!("Failed to upload wav: {}", e);
-                return
+/// Find reactions, edits and thread replies sent by the bot that relate to the given event.
+async fn find_own_relations(room: &Room, event_id: OwnedEventId) -> anyhow::Result<Vec<OwnedEventId>> {
+    let client = room.client();
+    let mut result = Vec::new();
+    let mut from = None;
+    loop {
+        let request = assign!(get_relating_events::v1::Request::new(room.room_id().to_owned(), event_id.clone()), {
+            from: from,
+        });
+        let response = client.send(request).await?;
+        for related in response.chunk {
+            let sender = related.get_field::<String>("sender")?;
+            if sender.as_deref() != Some(room.own_user_id().as_str()) {
+                continue;
             }
-        };
-
-        debug!("TTS uploaded");
+            if let Some(related_id) = related.get_field::<OwnedEventId>("event_id")? {
+                result.push(related_id);
+            }
+        }
+        from = response.next_batch;
+        if from.is_none() {
+            break;
+        }
+    }
+    Ok(result)
+}
 
-        let audio_content = AudioMessageEventContent::plain(
-            "TTS".to_string(),
-            upload.content_uri.into(),
+async fn handle_redact_spam(
+    mut args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    config: Config
+) {
+    let vip = is_user_vip(&event.sender, config.clone());
+    let trusted = is_user_trusted_not_vip(&event.sender, config.clone());
+    debug!("Got !redactspam in {} from {}, vip={vip}, trusted={trusted}", room.room_id(), event.sender);
+    let max_spam_count = if room.is_public().unwrap_or(true) {
+        1
+    } else if vip {
+        config.get::<usize>("bot.text_spam.vip_limit").unwrap_or(500)
+    } else if trusted {
+        config.get::<usize>("bot.text_spam.trusted_limit").unwrap_or(100)
+    } else {
+        return;
+    };
+    let desired_count = args.next().unwrap_or_default().parse::<usize>();
+    let custom_count = desired_count.is_ok();
+    let count = cmp::min(desired_count.unwrap_or(1), max_spam_count);
+    tokio::spawn(async move {
+        for i in 0..count {
+            let spam_select = TEXT_SPAM[i % TEXT_SPAM.len()];
+            let msg = if custom_count { format!("{} - {spam_select}", i+1) } else { spam_select.to_string() };
+            let content = RoomMessageEventContent::text_plain(msg);
+            let response = match room.send(content).await {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("Failed to redact-spam in {}: {}", room.room_id(), e);
+                    return;
+                }
+            };
+            if let Err(e) = room.redact(&response.event_id, Some("Spam"), None).await {
+                warn!("Failed to redact spam {} in {}: {}", response.event_id, room.room_id(), e);
+                return;
+            }
+        }
+    });
+}
+
+async fn handle_poll(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    config: Config,
+) {
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
+        return;
+    }
+    debug!("Got !poll in {} from {}", room.room_id(), event.sender);
+
+    let mut question = None;
+    let mut answers = Vec::new();
+    let mut kind = PollKind::Disclosed;
+    let mut max_selections: u64 = 1;
+    let mut stable = false;
+    for arg in split_quoted_args(command_text(event.content.body(), &args)) {
+        if arg == "--undisclosed" {
+            kind = PollKind::Undisclosed;
+        } else if arg == "--stable" {
+            stable = true;
+        } else if let Some(max) = arg.strip_prefix("--max=") {
+            max_selections = max.parse::<u64>().unwrap_or(1);
+        } else if question.is_none() {
+            question = Some(arg);
+        } else {
+            answers.push(arg);
+        }
+    }
+    let question = question.unwrap_or("What's your favourite spam?".to_string());
+    if answers.is_empty() {
+        answers = POLL_ANSWERS.iter().map(|a| a.to_string()).collect();
+    }
+    // Clients can't vote on polls allowing no selections
+    let max_selections = max_selections.clamp(1, answers.len() as u64);
+
+    tokio::spawn(async move {
+        if let Err(e) = send_poll(&room, &question, &answers, kind, max_selections, stable).await {
+            warn!("Failed to send poll in {}: {}", room.room_id(), e);
+            let content = RoomMessageEventContent::notice_plain(format!("Failed to start poll: {e}"));
+            if let Err(e) = room.send(content).await {
+                warn!("Failed to send poll error message in {}: {}", room.room_id(), e);
+            }
+        }
+    });
+}
+
+async fn handle_poll_spam(
+    mut args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    config: Config
+) {
+    let vip = is_user_vip(&event.sender, config.clone());
+    let trusted = is_user_trusted_not_vip(&event.sender, config.clone());
+    debug!("Got !pollspam in {} from {}, vip={vip}, trusted={trusted}", room.room_id(), event.sender);
+    let max_spam_count = if room.is_public().unwrap_or(true) {
+        1
+    } else if vip {
+        config.get::<usize>("bot.text_spam.vip_limit").unwrap_or(500)
+    } else if trusted {
+        config.get::<usize>("bot.text_spam.trusted_limit").unwrap_or(100)
+    } else {
+        return;
+    };
+    let desired_count = args.next().unwrap_or_default().parse::<usize>();
+    let count = cmp::min(desired_count.unwrap_or(3), max_spam_count);
+    tokio::spawn(async move {
+        for i in 0..count {
+            let question = format!("{} - {}", i+1, TEXT_SPAM[i % TEXT_SPAM.len()]);
+            // Mix up poll variants so clients get to render all of them
+            let answers: Vec<String> = POLL_ANSWERS.iter().take(2 + i % (POLL_ANSWERS.len() - 1)).map(|a| a.to_string()).collect();
+            let kind = if i % 2 == 0 { PollKind::Disclosed } else { PollKind::Undisclosed };
+            let max_selections = (i % answers.len() + 1) as u64;
+            let stable = i % 4 >= 2;
+            if let Err(e) = send_poll(&room, &question, &answers, kind, max_selections, stable).await {
+                warn!("Failed to poll-spam in {}: {}", room.room_id(), e);
+                return;
+            }
+        }
+    });
+}
+
+async fn send_poll(
+    room: &Room,
+    question: &str,
+    answers: &[String],
+    kind: PollKind,
+    max_selections: u64,
+    stable: bool,
+) -> anyhow::Result<OwnedEventId> {
+    let max_selections = UInt::new(max_selections).unwrap_or(UInt::MIN);
+    let fallback_text = format!(
+        "{question}\n{}",
+        answers.iter().enumerate().map(|(i, a)| format!("{}. {a}", i+1)).collect::<Vec<_>>().join("\n"),
+    );
+    let response = if stable {
+        let answers = PollAnswers::try_from(
+            answers.iter().enumerate().map(|(i, a)|
+                PollAnswer::new(format!("answer-{}", i+1), TextContentBlock::plain(a))
+            ).collect::<Vec<_>>()
+        ).map_err(|e| anyhow::anyhow!("{e}"))?;
+        let poll = assign!(PollContentBlock::new(TextContentBlock::plain(question), answers), {
+            kind: kind,
+            max_selections: max_selections,
+        });
+        room.send(PollStartEventContent::with_plain_text(fallback_text, poll)).await?
+    } else {
+        let answers = UnstablePollAnswers::try_from(
+            answers.iter().enumerate().map(|(i, a)|
+                UnstablePollAnswer::new(format!("answer-{}", i+1), a)
+            ).collect::<Vec<_>>()
+        ).map_err(|e| anyhow::anyhow!("{e}"))?;
+        let poll = assign!(UnstablePollStartContentBlock::new(question, answers), {
+            kind: kind,
+            max_selections: max_selections,
+        });
+        let content = NewUnstablePollStartEventContent::plain_text(fallback_text, poll);
+        room.send(UnstablePollStartEventContent::from(content)).await?
+    };
+    Ok(response.event_id)
+}
+
+/// Look up the answer IDs of a poll, and whether it was sent with the stable event type.
+async fn fetch_poll_answers(room: &Room, event_id: &OwnedEventId) -> anyhow::Result<(bool, Vec<String>)> {
+    let event = room.event(event_id, None).await?;
+    let event: serde_json::Value = serde_json::from_str(event.into_raw().json().get())?;
+    let (stable, answers, id_key) = match event["type"].as_str() {
+        Some("m.poll.start") => (true, &event["content"]["m.poll"]["answers"], "m.id"),
+        Some("org.matrix.msc3381.poll.start") => (false, &event["content"]["org.matrix.msc3381.poll.start"]["answers"], "id"),
+        _ => anyhow::bail!("Not a poll"),
+    };
+    let answers = answers.as_array()
+        .map(|answers| answers.iter().filter_map(|a| a[id_key].as_str().map(|id| id.to_string())).collect())
+        .unwrap_or_default();
+    Ok((stable, answers))
+}
+
+async fn handle_poll_vote(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let trusted = is_user_trusted(&event.sender, context.config.clone());
+    if !trusted {
+        return;
+    }
+    let poll_id = if let Some(Relation::Reply { in_reply_to }) = event.content.relates_to {
+        in_reply_to.event_id
+    } else {
+        debug!("Got !vote without reply in {} from {}", room.room_id(), event.sender);
+        let content =
+            RoomMessageEventContent::notice_plain("Please reply to a poll while issuing the !vote command");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send error message in {}: {}", room.room_id(), e);
+        }
+        return;
+    };
+    debug!("Got !vote in {} from {}", room.room_id(), event.sender);
+    let selections: Vec<usize> = args.filter_map(|a| a.parse::<usize>().ok()).collect();
+
+    // Prefer voting from the media account, so the bot's own poll gets a vote from someone else
+    let voting_room = context.media_client
+        .and_then(|client| client.get_room(room.room_id()))
+        .unwrap_or_else(|| room.clone());
+
+    tokio::spawn(async move {
+        let (stable, answers) = match fetch_poll_answers(&room, &poll_id).await {
+            Ok(a) => a,
+            Err(e) => {
+                warn!("Failed to look up poll {} in {}: {}", poll_id, room.room_id(), e);
+                let content = RoomMessageEventContent::notice_plain("Replied-to message does not appear to be a poll");
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send vote error message in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+        };
+        let votes: Vec<String> = if selections.is_empty() {
+            answers.get(rand::random_range(0..cmp::max(answers.len(), 1))).cloned().into_iter().collect()
+        } else {
+            // Out-of-range selections are passed through as invalid answer IDs on purpose
+            selections.iter().map(|i| answers.get(i.wrapping_sub(1)).cloned().unwrap_or(format!("answer-{i}"))).collect()
+        };
+        let result = if stable {
+            voting_room.send(PollResponseEventContent::new(SelectionsContentBlock::from(votes), poll_id)).await
+        } else {
+            voting_room.send(UnstablePollResponseEventContent::new(votes, poll_id)).await
+        };
+        if let Err(e) = result {
+            warn!("Failed to vote in {}: {}", voting_room.room_id(), e);
+        }
+    });
+}
+
+async fn handle_poll_end(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    config: Config,
+) {
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
+        return;
+    }
+    let poll_id = if let Some(Relation::Reply { in_reply_to }) = event.content.relates_to {
+        in_reply_to.event_id
+    } else {
+        debug!("Got !pollend without reply in {} from {}", room.room_id(), event.sender);
+        let content =
+            RoomMessageEventContent::notice_plain("Please reply to a poll while issuing the !pollend command");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send error message in {}: {}", room.room_id(), e);
+        }
+        return;
+    };
+    debug!("Got !pollend in {} from {}", room.room_id(), event.sender);
+    tokio::spawn(async move {
+        let stable = match fetch_poll_answers(&room, &poll_id).await {
+            Ok((stable, _)) => stable,
+            Err(e) => {
+                warn!("Failed to look up poll {} in {}: {}", poll_id, room.room_id(), e);
+                let content = RoomMessageEventContent::notice_plain("Replied-to message does not appear to be a poll");
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send poll end error message in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+        };
+        let result = if stable {
+            room.send(PollEndEventContent::with_plain_text("The poll has ended", poll_id)).await
+        } else {
+            room.send(UnstablePollEndEventContent::new("The poll has ended", poll_id)).await
+        };
+        if let Err(e) = result {
+            warn!("Failed to end poll in {}: {}", room.room_id(), e);
+        }
+    });
+}
+
+async fn handle_location(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    config: Config,
+) {
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
+        return;
+    }
+    debug!("Got !location in {} from {}", room.room_id(), event.sender);
+
+    let mut asset = None;
+    let mut rest = Vec::new();
+    for arg in args {
+        if let Some(a) = arg.strip_prefix("--asset=") {
+            asset = Some(format!("m.{a}"));
+        } else {
+            rest.push(arg);
+        }
+    }
+    let (lat, lon, description) = match (rest.first().and_then(|a| a.parse::<f64>().ok()), rest.get(1).and_then(|a| a.parse::<f64>().ok())) {
+        (Some(lat), Some(lon)) => (lat, lon, rest[2..].join(" ")),
+        _ => (rand::random_range(-80.0..80.0), rand::random_range(-180.0..180.0), rest.join(" ")),
+    };
+    let geo_uri = format!("geo:{lat:.6},{lon:.6}");
+    let body = if description.is_empty() {
+        format!("Location {geo_uri}")
+    } else {
+        description.clone()
+    };
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+
+    // Built by hand since ruma only exposes the MSC3488 fields behind unstable features
+    let mut content = serde_json::json!({
+        "msgtype": "m.location",
+        "body": body,
+        "geo_uri": geo_uri,
+        "org.matrix.msc3488.location": {
+            "uri": geo_uri,
+        },
+        "org.matrix.msc1767.text": body,
+        "org.matrix.msc3488.ts": ts,
+    });
+    if !description.is_empty() {
+        content["org.matrix.msc3488.location"]["description"] = description.into();
+    }
+    if let Some(asset) = asset {
+        content["org.matrix.msc3488.asset"] = serde_json::json!({ "type": asset });
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = room.send_raw("m.room.message", content).await {
+            warn!("Failed to send location in {}: {}", room.room_id(), e);
+        }
+    });
+}
+
+async fn handle_live_location(
+    mut args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    config: Config,
+) {
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
+        return;
+    }
+
+    let desired_duration = args.next().unwrap_or_default().parse::<u64>();
+    debug!("Got !livelocation ({}) in {} from {}", desired_duration.clone().unwrap_or_default(), room.room_id(), event.sender);
+
+    let max_duration = config.get::<u64>("bot.live_location.max_duration").unwrap_or(300);
+    let duration = cmp::min(desired_duration.unwrap_or(60), max_duration);
+    let description = args.collect::<Vec<_>>().join(" ");
+    let description = if description.is_empty() { None } else { Some(description) };
+
+    tokio::spawn(async move {
+        if let Err(e) = room.start_live_location_share(duration * 1000, description).await {
+            warn!("Failed to start live location share in {}: {}", room.room_id(), e);
+            let content = RoomMessageEventContent::notice_plain("Failed to start live location share");
+            if let Err(e) = room.send(content).await {
+                warn!("Failed to send live location error message in {}: {}", room.room_id(), e);
+            }
+            return;
+        }
+
+        // Wander around a random starting point so clients have something to move on the map
+        let beacon_period = 5;
+        let mut lat: f64 = rand::random_range(-80.0..80.0);
+        let mut lon: f64 = rand::random_range(-180.0..180.0);
+        let mut remaining = duration;
+        loop {
+            let geo_uri = format!("geo:{lat:.6},{lon:.6};u=10");
+            if let Err(e) = room.send_location_beacon(geo_uri).await {
+                warn!("Failed to send location beacon in {}: {}", room.room_id(), e);
+                break;
+            }
+            if remaining < beacon_period {
+                break;
+            }
+            sleep(Duration::from_secs(beacon_period)).await;
+            remaining -= beacon_period;
+            lat = (lat + rand::random_range(-0.001..0.001)).clamp(-90.0, 90.0);
+            lon += rand::random_range(-0.001..0.001);
+        }
+
+        if let Err(e) = room.stop_live_location_share().await {
+            warn!("Failed to stop live location share in {}: {}", room.room_id(), e);
+        } else {
+            debug!("Finished live location share after {duration} in {}", room.room_id());
+        }
+    });
+}
+
+async fn handle_file(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let config = context.config;
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
+        return;
+    }
+
+    let mut claimed_size = None;
+    let mut claimed_mimetype = None;
+    let mut long_name = None;
+    let mut unicode_name = false;
+    let mut fresh = false;
+    let mut positional = Vec::new();
+    for arg in split_quoted_args(command_text(event.content.body(), &args)) {
+        if let Some(size) = arg.strip_prefix("--info-size=") {
+            claimed_size = file_generator::parse_size(size);
+        } else if let Some(mimetype) = arg.strip_prefix("--info-mimetype=") {
+            claimed_mimetype = Some(mimetype.to_string());
+        } else if arg == "--long-name" {
+            long_name = Some(300);
+        } else if let Some(length) = arg.strip_prefix("--long-name=") {
+            long_name = length.parse::<usize>().ok();
+        } else if arg == "--unicode-name" {
+            unicode_name = true;
+        } else if arg == "--fresh" {
+            fresh = true;
+        } else {
+            positional.push(arg);
+        }
+    }
+    let mut positional = positional.into_iter();
+
+    let max_size = config.get::<usize>("bot.file.max_size").unwrap_or(10_000_000);
+    let size = cmp::min(positional.next().and_then(|s| file_generator::parse_size(&s)).unwrap_or(1024), max_size);
+    let mimetype = positional.next().unwrap_or(mime::APPLICATION_OCTET_STREAM.to_string());
+    let extension = file_generator::extension_for(&mimetype);
+    let filename = if let Some(filename) = positional.next() {
+        filename
+    } else if let Some(length) = long_name {
+        let stem: String = "very-long-file-name-".chars().cycle().take(length.saturating_sub(extension.len() + 1)).collect();
+        format!("{stem}.{extension}")
+    } else if unicode_name {
+        format!("Späm 🥫 スパム спам سبام ∑pam.{extension}")
+    } else {
+        format!("spam.{extension}")
+    };
+    debug!("Got !file ({size}, {mimetype}, {filename}) in {} from {}", room.room_id(), event.sender);
+
+    let upload_mimetype = match mimetype.parse::<mime::Mime>() {
+        Ok(m) => m,
+        Err(e) => {
+            let content = RoomMessageEventContent::notice_plain(format!("Invalid mimetype {mimetype}: {e}"));
+            if let Err(e) = room.send(content).await {
+                warn!("Failed to send file error message in {}: {}", room.room_id(), e);
+            }
+            return;
+        }
+    };
+
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache;
+
+    tokio::spawn(async move {
+        let generated_mimetype = upload_mimetype.essence_str().to_string();
+        let file = match tokio::task::spawn_blocking(move || file_generator::create_file(&generated_mimetype, size)).await {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Failed to generate file: {}", e);
+                return
+            }
+        };
+        let file_size = file.len();
+
+        let file_uri = match upload_cache.upload(&media_client, &upload_mimetype, file, fresh).await {
+            Ok(mxc) => mxc,
+            Err(e) => {
+                error!("Failed to upload file: {}", e);
+                return
+            }
+        };
+
+        let file_info = assign!(FileInfo::new(), {
+            size: claimed_size.unwrap_or(file_size).try_into().ok(),
+            mimetype: Some(claimed_mimetype.unwrap_or(upload_mimetype.essence_str().to_string())),
+        });
+        let file_content = assign!(FileMessageEventContent::plain(
+            filename.clone(),
+            file_uri.clone(),
+        ), {
+            filename: Some(filename),
+        }).info(Some(Box::new(file_info)));
+
+        let message = RoomMessageEventContent::new(
+            MessageType::File(file_content)
         );
 
-        let message = RoomMessageEventContent::new(
-            MessageType::Audio(audio_content)
-        );
+        if let Err(e) = room.send(message).await {
+            warn!("Failed to send file in {}: {}", room.room_id(), e);
+            upload_cache.forget(&file_uri).await;
+            return;
+        }
+
+        trace!("Successfully sent file with size {file_size} and mxc {}", file_uri);
+    });
+}
+
+// Maximum upload size the homeserver advertises, `m.upload.size` of the media config
+async fn fetch_upload_limit(client: &Client) -> anyhow::Result<u64> {
+    let response = client.send(get_media_config::v1::Request::new()).await?;
+    Ok(response.upload_size.into())
+}
+
+async fn handle_upload_limit(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let trusted = is_user_trusted(&event.sender, context.config.clone());
+    debug!("Got !uploadlimit in {} from {}, trusted={trusted}", room.room_id(), event.sender);
+    if !trusted {
+        return;
+    }
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+
+    tokio::spawn(async move {
+        let msg = match fetch_upload_limit(&media_client).await {
+            Ok(limit) => format!(
+                "Upload limit of {}: {limit} bytes ({:.2} MB)",
+                media_client.homeserver(),
+                limit as f64 / 1_000_000.0,
+            ),
+            Err(e) => format!("Failed to get media config of {}: {e}", media_client.homeserver()),
+        };
+        if let Err(e) = room.send(RoomMessageEventContent::notice_plain(msg)).await {
+            warn!("Failed to send upload limit in {}: {}", room.room_id(), e);
+        }
+    });
+}
+
+async fn handle_big_file(
+    mut args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let config = context.config;
+    let vip = is_user_vip(&event.sender, config.clone());
+    debug!("Got !bigfile in {} from {}, vip={vip}", room.room_id(), event.sender);
+    if !vip {
+        return;
+    }
+    // Everything is generated in memory, so don't go crazy even if the server would accept it
+    let max_size = config.get::<u64>("bot.file.big_max_size").unwrap_or(200_000_000);
+    let requested_size = args.next().and_then(|mb| mb.parse::<f64>().ok()).map(|mb| (mb * 1_000_000.0) as u64);
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+
+    tokio::spawn(async move {
+        let limit = match fetch_upload_limit(&media_client).await {
+            Ok(limit) => limit,
+            Err(e) => {
+                let content = RoomMessageEventContent::notice_plain(format!("Failed to get upload limit: {e}"));
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send bigfile error message in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+        };
+
+        // Up to the limit, and always one byte above it to see whether it's enforced
+        let sizes = [requested_size.unwrap_or(limit).min(limit).min(max_size), limit.saturating_add(1)];
+
+        let mut lines = vec![format!("Upload limit: {limit} bytes")];
+        for size in sizes {
+            if size > max_size {
+                lines.push(format!("- {size} bytes: skipped, above the configured maximum of {max_size} bytes"));
+                continue;
+            }
+            let Ok(file_size) = usize::try_from(size) else {
+                continue;
+            };
+            let file = match tokio::task::spawn_blocking(move || file_generator::create_random(file_size)).await {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("Failed to generate big file: {}", e);
+                    return;
+                }
+            };
+            let started = std::time::Instant::now();
+            let result = media_client.media().upload(&mime::APPLICATION_OCTET_STREAM, file, None).await;
+            let elapsed = started.elapsed();
+            let throughput = size as f64 / 1_000_000.0 / elapsed.as_secs_f64().max(0.001);
+            match result {
+                Ok(upload) => {
+                    lines.push(format!(
+                        "- {size} bytes: uploaded to {} in {:.1}s ({throughput:.2} MB/s)",
+                        upload.content_uri,
+                        elapsed.as_secs_f64(),
+                    ));
+                    let filename = format!("bigfile-{size}.bin");
+                    let file_info = assign!(FileInfo::new(), {
+                        size: size.try_into().ok(),
+                        mimetype: Some(mime::APPLICATION_OCTET_STREAM.to_string()),
+                    });
+                    let file_content = assign!(FileMessageEventContent::plain(filename.clone(), upload.content_uri), {
+                        filename: Some(filename),
+                    }).info(Some(Box::new(file_info)));
+                    if let Err(e) = room.send(RoomMessageEventContent::new(MessageType::File(file_content))).await {
+                        warn!("Failed to send big file in {}: {}", room.room_id(), e);
+                    }
+                }
+                Err(e) => lines.push(format!("- {size} bytes: failed after {:.1}s: {e}", elapsed.as_secs_f64())),
+            }
+        }
+
+        if let Err(e) = room.send(RoomMessageEventContent::notice_markdown(lines.join("\n"))).await {
+            warn!("Failed to send bigfile results in {}: {}", room.room_id(), e);
+        }
+    });
+}
+
+async fn handle_video(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let config = context.config;
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
+        return;
+    }
+    let (options, args): (Vec<&str>, Vec<&str>) = args.partition(|a| a.starts_with("--"));
+    let mut args = args.into_iter();
+    let fresh = options.contains(&"--fresh");
+    let max_size = config.get::<usize>("bot.video.max_size").unwrap_or(640);
+    let max_duration = config.get::<u32>("bot.video.max_duration").unwrap_or(10);
+    let fps = cmp::max(config.get::<u32>("bot.video.fps").unwrap_or(5), 1);
+    let width = cmp::min(args.next().unwrap_or_default().parse::<usize>().unwrap_or(160), max_size);
+    let height = cmp::min(args.next().unwrap_or_default().parse::<usize>().unwrap_or(width * 3 / 4), max_size);
+    let seconds = cmp::min(args.next().unwrap_or_default().parse::<u32>().unwrap_or(3), max_duration);
+    // 4:2:0 chroma subsampling needs even dimensions
+    let width = cmp::max(width + width % 2, 2);
+    let height = cmp::max(height + height % 2, 2);
+    let claimed_width = args.next().unwrap_or_default().parse::<usize>().unwrap_or(width);
+    let claimed_height = args.next().unwrap_or_default().parse::<usize>().unwrap_or(height);
+    let frame_count = cmp::max(seconds * fps, 1);
+    let font_size = 42.0 * ((cmp::min(width, height) as f64)/150.0);
+    debug!("Got !video {width}x{height} {seconds}s in {} from {}", room.room_id(), event.sender);
+
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache;
+
+    tokio::spawn(async move {
+        let start_hue: f64 = rand::random_range(0.0..360.0);
+        // Drawing every frame and writing the uncompressed stream takes a while for long videos
+        let video = tokio::task::spawn_blocking(move || {
+            let mut frames = Vec::with_capacity(frame_count as usize);
+            for i in 0..frame_count {
+                let text = format!("{}", i / fps + 1);
+                let background_color = image_generator::hue_color(start_hue + 360.0 * i as f64 / frame_count as f64);
+                frames.push(image_generator::create_text_image_rgb(
+                    Some(&text),
+                    &background_color,
+                    "#ffffff",
+                    width,
+                    height,
+                    font_size,
+                )?);
+            }
+            anyhow::Ok(video_generator::create_mp4(&frames, width, height, fps))
+        }).await;
+        let video = match video {
+            Ok(Ok(video)) => video,
+            Ok(Err(e)) => {
+                error!("Failed to generate video frame: {}", e);
+                return;
+            }
+            Err(e) => {
+                error!("Failed to generate video: {}", e);
+                return;
+            }
+        };
+        let video_size = video.len();
+
+        let poster = match image_generator::create_text_image(
+            Some(&"▶".to_string()),
+            &image_generator::hue_color(start_hue),
+            "#ffffff",
+            width,
+            height,
+            font_size,
+            &ImageEncoding::default(),
+        ).await {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Failed to generate video poster: {}", e);
+                return;
+            }
+        };
+        let thumbnail_info = assign!(ThumbnailInfo::new(), {
+            width: width.try_into().ok(),
+            height: height.try_into().ok(),
+            size: poster.len().try_into().ok(),
+            mimetype: Some(mime::IMAGE_PNG.to_string()),
+        });
+        let thumbnail_uri = match upload_cache.upload(&media_client, &mime::IMAGE_PNG, poster, fresh).await {
+            Ok(mxc) => mxc,
+            Err(e) => {
+                error!("Failed to upload video poster: {}", e);
+                return
+            }
+        };
+
+        let video_mime: mime::Mime = "video/mp4".parse().expect("Valid mimetype");
+        let video_uri = match upload_cache.upload(&media_client, &video_mime, video, fresh).await {
+            Ok(mxc) => mxc,
+            Err(e) => {
+                error!("Failed to upload video: {}", e);
+                return
+            }
+        };
+
+        let video_info = assign!(VideoInfo::new(), {
+            duration: Some(Duration::from_millis(frame_count as u64 * 1000 / fps as u64)),
+            width: claimed_width.try_into().ok(),
+            height: claimed_height.try_into().ok(),
+            size: video_size.try_into().ok(),
+            mimetype: Some(video_mime.essence_str().to_string()),
+            thumbnail_info: Some(Box::new(thumbnail_info)),
+            thumbnail_source: Some(MediaSource::Plain(thumbnail_uri.clone())),
+        });
+        let video_content = VideoMessageEventContent::plain(
+            "video.mp4".to_string(),
+            video_uri.clone(),
+        ).info(Some(Box::new(video_info)));
+
+        let message = RoomMessageEventContent::new(
+            MessageType::Video(video_content)
+        );
+
+        if let Err(e) = room.send(message).await {
+            warn!("Failed to send video in {}: {}", room.room_id(), e);
+            upload_cache.forget(&video_uri).await;
+            upload_cache.forget(&thumbnail_uri).await;
+            return;
+        }
+
+        trace!("Successfully sent video with size {video_size}, mxc {} and poster {}", video_uri, thumbnail_uri);
+    });
+}
+
+async fn handle_animated(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+    as_sticker: bool,
+) {
+    let config = context.config;
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
+        return;
+    }
+    let (options, args): (Vec<&str>, Vec<&str>) = args.partition(|a| a.starts_with("--"));
+    let mut args = args.into_iter();
+    let fresh = options.contains(&"--fresh");
+    let max_size = config.get::<usize>("bot.image_spam.max_size").unwrap_or(500);
+    let max_frames = config.get::<usize>("bot.image_spam.max_frames").unwrap_or(50);
+    let frame_count = cmp::max(cmp::min(args.next().unwrap_or_default().parse::<usize>().unwrap_or(10), max_frames), 1);
+    let format = AnimationFormat::parse(args.next().unwrap_or_default()).unwrap_or(AnimationFormat::Gif);
+    let width = cmp::max(cmp::min(args.next().unwrap_or_default().parse::<usize>().unwrap_or(150), max_size), 1);
+    let height = cmp::max(cmp::min(args.next().unwrap_or_default().parse::<usize>().unwrap_or(width), max_size), 1);
+    let font_size = 64.0 * ((cmp::min(width, height) as f64)/150.0);
+    debug!("Got !animated {frame_count} {format:?} {width}x{height} in {} from {}, sticker={as_sticker}", room.room_id(), event.sender);
+
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache;
+
+    tokio::spawn(async move {
+        let image = tokio::task::spawn_blocking(move || image_generator::create_animated_counter_image(
+            frame_count,
+            format,
+            width,
+            height,
+            font_size,
+            500,
+        )).await;
+        let image = match image {
+            Ok(Ok(i)) => i,
+            Ok(Err(e)) => {
+                error!("Failed to generate animated image: {}", e);
+                let content = RoomMessageEventContent::notice_plain(format!("Failed to generate animated image: {e}"));
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send animated image error message in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+            Err(e) => {
+                error!("Failed to generate animated image: {}", e);
+                return;
+            }
+        };
+        let image_size = image.len();
+        let image_mime: mime::Mime = format.mimetype().parse().expect("Valid mimetype");
+
+        let image_uri = match upload_cache.upload(&media_client, &image_mime, image, fresh).await {
+            Ok(mxc) => mxc,
+            Err(e) => {
+                error!("Failed to upload animated image: {}", e);
+                return
+            }
+        };
+
+        let image_info = assign!(ImageInfo::new(), {
+            width: width.try_into().ok(),
+            height: height.try_into().ok(),
+            size: image_size.try_into().ok(),
+            mimetype: Some(image_mime.essence_str().to_string()),
+        });
+        let body = format!("animated.{}", format.extension());
+
+        let result = if as_sticker {
+            room.send(StickerEventContent::new(
+                body,
+                image_info,
+                image_uri.clone(),
+            )).await
+        } else {
+            let image_content = ImageMessageEventContent::plain(
+                body,
+                image_uri.clone(),
+            ).info(Some(Box::new(image_info)));
+            room.send(RoomMessageEventContent::new(
+                MessageType::Image(image_content)
+            )).await
+        };
+        if let Err(e) = result {
+            warn!("Failed to send animated image in {}: {}", room.room_id(), e);
+            upload_cache.forget(&image_uri).await;
+            return;
+        }
+
+        trace!("Successfully sent animated image with size {image_size} and mxc {}", image_uri);
+    });
+}
+
+async fn handle_render(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let config = context.config;
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
+        return;
+    }
+    debug!("Got !render in {} from {}", room.room_id(), event.sender);
+
+    // Everything after the options is rendered as-is including line breaks
+    let (options, text) = leading_options(command_text(event.content.body(), &args));
+    if text.is_empty() {
+        let content = RoomMessageEventContent::notice_plain("Usage: `!render [options] <text>`");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send render usage in {}: {}", room.room_id(), e);
+        }
+        return;
+    }
+    let text = text.to_string();
+
+    let max_size = config.get::<usize>("bot.image_spam.max_size").unwrap_or(500);
+    let option_value = |prefix: &str| options.iter().find_map(|o| o.strip_prefix(prefix));
+    let as_sticker = options.contains(&"--sticker");
+    let mut fonts = match option_value("--font=") {
+        Some(font) => vec![font.replace('_', " ")],
+        None => vec![config.get::<String>("bot.render.font").unwrap_or("DejaVu Sans".to_string())],
+    };
+    fonts.extend(config.get::<Vec<String>>("bot.render.fallback_fonts").unwrap_or_default());
+    let font_size = option_value("--size=")
+        .and_then(|s| s.parse::<f64>().ok())
+        .unwrap_or(config.get::<f64>("bot.render.font_size").unwrap_or(24.0))
+        .clamp(1.0, max_size as f64);
+    let max_width = option_value("--width=")
+        .and_then(|w| w.parse::<usize>().ok())
+        .unwrap_or(config.get::<usize>("bot.render.max_width").unwrap_or(800))
+        .clamp(1, max_size);
+    let background_color = option_value("--bg=").unwrap_or(if as_sticker { "none" } else { "#ffffff" }).to_string();
+    let foreground_color = option_value("--fg=").unwrap_or("#000000").to_string();
+    // The foreground color ends up in pango markup, the background only goes to ImageMagick
+    if !is_color(&foreground_color) {
+        let content = RoomMessageEventContent::notice_plain("Colors are `#rrggbb` or a color name");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send render usage in {}: {}", room.room_id(), e);
+        }
+        return;
+    }
+    let encoding = parse_image_encoding(&options);
+    let fresh = options.contains(&"--fresh");
+
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache;
+
+    tokio::spawn(async move {
+        let rendered = {
+            let text = text.clone();
+            let encoding = encoding.clone();
+            tokio::task::spawn_blocking(move || {
+                let rendering = image_generator::TextRendering {
+                    fonts: &fonts,
+                    font_size,
+                    max_width,
+                    max_height: max_size,
+                    padding: (font_size / 2.0) as usize,
+                    background_color: &background_color,
+                    foreground_color: &foreground_color,
+                };
+                image_generator::render_text(&text, &rendering, &encoding)
+            }).await
+        };
+        let (image, width, height) = match rendered {
+            Ok(Ok(i)) => i,
+            Ok(Err(e)) => {
+                error!("Failed to render text: {}", e);
+                let content = RoomMessageEventContent::notice_plain(format!("Failed to render text: {e}"));
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send render error message in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+            Err(e) => {
+                error!("Failed to render text: {}", e);
+                return;
+            }
+        };
+        let image_size = image.len();
+        let image_mime: mime::Mime = encoding.format.mimetype().parse().expect("Valid mimetype");
+
+        let image_uri = match upload_cache.upload(&media_client, &image_mime, image, fresh).await {
+            Ok(mxc) => mxc,
+            Err(e) => {
+                error!("Failed to upload rendered text: {}", e);
+                return
+            }
+        };
+
+        let image_info = assign!(ImageInfo::new(), {
+            width: width.try_into().ok(),
+            height: height.try_into().ok(),
+            size: image_size.try_into().ok(),
+            mimetype: Some(image_mime.essence_str().to_string()),
+        });
+
+        let result = if as_sticker {
+            room.send(StickerEventContent::new(
+                text,
+                image_info,
+                image_uri.clone(),
+            )).await
+        } else {
+            let image_content = ImageMessageEventContent::plain(
+                format!("render.{}", encoding.format.extension()),
+                image_uri.clone(),
+            ).info(Some(Box::new(image_info)));
+            room.send(RoomMessageEventContent::new(
+                MessageType::Image(image_content)
+            )).await
+        };
+        if let Err(e) = result {
+            warn!("Failed to send rendered text in {}: {}", room.room_id(), e);
+            upload_cache.forget(&image_uri).await;
+            return;
+        }
+
+        trace!("Successfully sent rendered text with size {width}x{height} and mxc {}", image_uri);
+    });
+}
+
+async fn handle_qr(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let trusted = is_user_trusted(&event.sender, context.config.clone());
+    if !trusted {
+        return;
+    }
+    debug!("Got !qr in {} from {}", room.room_id(), event.sender);
+
+    let (options, text) = leading_options(command_text(event.content.body(), &args));
+    let fresh = options.contains(&"--fresh");
+    let text = text.to_string();
+    let in_reply_to = if let Some(Relation::Reply { in_reply_to }) = &event.content.relates_to {
+        Some(in_reply_to.event_id.clone())
+    } else {
+        None
+    };
+    if text.is_empty() && in_reply_to.is_none() {
+        let content = RoomMessageEventContent::notice_plain("Usage: `!qr <text>`, or reply with `!qr [link]`");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send qr usage in {}: {}", room.room_id(), e);
+        }
+        return;
+    }
+
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache;
+
+    tokio::spawn(async move {
+        let text = match in_reply_to {
+            Some(event_id) if text == "link" => room.matrix_to_event_permalink(event_id).await
+                .map(|uri| uri.to_string())
+                .map_err(anyhow::Error::from),
+            Some(event_id) if text.is_empty() => fetch_message(&room, &event_id).await.map(|content| plain_text(&content)),
+            _ => Ok(text),
+        };
+        let text = match text {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("Failed to look up replied-to event for qr in {}: {}", room.room_id(), e);
+                let content = RoomMessageEventContent::notice_plain("Failed to look-up replied-to event");
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send qr error message in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+        };
+
+        let encoding = ImageEncoding::default();
+        let (image, size) = match image_generator::create_qr_code(&text, 8, &encoding).await {
+            Ok(i) => i,
+            Err(e) => {
+                error!("Failed to generate QR code: {}", e);
+                let content = RoomMessageEventContent::notice_plain(format!("Failed to generate QR code: {e}"));
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send qr error message in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+        };
+        let image_size = image.len();
+        let image_mime: mime::Mime = encoding.format.mimetype().parse().expect("Valid mimetype");
+
+        let image_uri = match upload_cache.upload(&media_client, &image_mime, image, fresh).await {
+            Ok(mxc) => mxc,
+            Err(e) => {
+                error!("Failed to upload QR code: {}", e);
+                return
+            }
+        };
+
+        let image_info = assign!(ImageInfo::new(), {
+            width: size.try_into().ok(),
+            height: size.try_into().ok(),
+            size: image_size.try_into().ok(),
+            mimetype: Some(image_mime.essence_str().to_string()),
+        });
+        let image_content = ImageMessageEventContent::plain(
+            text,
+            image_uri.clone(),
+        ).info(Some(Box::new(image_info)));
+        if let Err(e) = room.send(RoomMessageEventContent::new(MessageType::Image(image_content))).await {
+            warn!("Failed to send QR code in {}: {}", room.room_id(), e);
+            upload_cache.forget(&image_uri).await;
+            return;
+        }
+
+        trace!("Successfully sent QR code with mxc {}", image_uri);
+    });
+}
+
+// Options at the start of a command's arguments, and the remaining text
+fn leading_options(mut text: &str) -> (Vec<&str>, &str) {
+    let mut options = Vec::new();
+    while let Some(option) = text.split_whitespace().next().filter(|o| o.starts_with("--")) {
+        options.push(option);
+        text = text[option.len()..].trim_start();
+    }
+    (options, text)
+}
+
+// `#rgb`, `#rrggbb` with optional alpha, or a color name like `red`
+fn is_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => !color.is_empty() && color.chars().all(|c| c.is_ascii_alphanumeric()),
+    }
+}
+
+// Message event content, for commands that act on replied-to messages. Encrypted events are
+// decrypted if we have the keys.
+async fn fetch_message(room: &Room, event_id: &OwnedEventId) -> anyhow::Result<RoomMessageEventContent> {
+    Ok(fetch_message_event(room, event_id).await?.content)
+}
+
+async fn fetch_message_event(room: &Room, event_id: &OwnedEventId) -> anyhow::Result<OriginalRoomMessageEvent> {
+    let event = room.event(event_id, None).await?
+        .into_raw()
+        .deserialize()?
+        .into_full_event(room.room_id().into());
+    match event {
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(MessageLikeEvent::Original(message))) => {
+            Ok(message)
+        }
+        _ => Err(anyhow::anyhow!("Event {event_id} is not a message")),
+    }
+}
+
+// Plain text body without the quoted message older clients add to replies
+fn plain_text(content: &RoomMessageEventContent) -> String {
+    let body = content.body();
+    if !body.starts_with("> ") {
+        return body.to_string();
+    }
+    let mut rest = body;
+    while rest.starts_with('>') {
+        rest = rest.split_once('\n').map(|p| p.1).unwrap_or_default();
+    }
+    rest.trim_start_matches('\n').to_string()
+}
+
+// Text suitable for reading aloud: the formatted body without markup if there is one,
+// since the plain body of formatted messages often contains markdown syntax
+fn speakable_text(content: &RoomMessageEventContent) -> String {
+    let formatted = match &content.msgtype {
+        MessageType::Text(text) => text.formatted.as_ref(),
+        MessageType::Notice(notice) => notice.formatted.as_ref(),
+        MessageType::Emote(emote) => emote.formatted.as_ref(),
+        _ => None,
+    };
+    match formatted {
+        Some(formatted) if formatted.format == MessageFormat::Html => strip_html(&formatted.body),
+        _ => plain_text(content),
+    }
+}
+
+fn strip_html(html: &str) -> String {
+    // Reply fallbacks in the formatted body are wrapped in mx-reply
+    let html = match (html.find("<mx-reply>"), html.find("</mx-reply>")) {
+        (Some(start), Some(end)) if start < end => format!("{}{}", &html[..start], &html[end + "</mx-reply>".len()..]),
+        _ => html.to_string(),
+    };
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html.as_str();
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_ascii_lowercase();
+        let tag_name = tag.split(|c: char| c.is_whitespace() || c == '/').find(|t| !t.is_empty()).unwrap_or_default();
+        if matches!(tag_name, "br" | "p" | "li" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "blockquote" | "pre" | "tr")
+            && (tag_name == "br" || tag.starts_with('/'))
+        {
+            text.push('\n');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+async fn handle_pack(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    config: Config,
+) {
+    let trusted = is_user_trusted(&event.sender, config);
+    if !trusted {
+        return;
+    }
+    let (options, args): (Vec<&str>, Vec<&str>) = args.partition(|a| a.starts_with("--"));
+    let mut args = args.into_iter();
+    let action = args.next().unwrap_or_default().to_ascii_lowercase();
+    debug!("Got !pack {action} in {} from {}", room.room_id(), event.sender);
+    let user_pack = options.contains(&"--user");
+    let usage: Vec<PackUsage> = [("--emoticon", PackUsage::Emoticon), ("--sticker", PackUsage::Sticker)]
+        .into_iter()
+        .filter(|(option, _)| options.contains(option))
+        .map(|(_, usage)| usage)
+        .collect();
+    let in_reply_to = if let Some(Relation::Reply { in_reply_to }) = &event.content.relates_to {
+        Some(in_reply_to.event_id.clone())
+    } else {
+        None
+    };
+
+    let (shortcode, pack_key) = match action.as_str() {
+        "create" => (None, args.next().unwrap_or_default().to_string()),
+        "add" | "remove" => match args.next() {
+            Some(shortcode) => (Some(image_pack::normalize_shortcode(shortcode)), args.next().unwrap_or_default().to_string()),
+            None => {
+                let content = RoomMessageEventContent::notice_plain(format!("Usage: `!pack {action} <shortcode> [pack]`"));
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send pack usage in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+        },
+        _ => {
+            let content = RoomMessageEventContent::notice_plain("Usage: `!pack create|add|remove ...`");
+            if let Err(e) = room.send(content).await {
+                warn!("Failed to send pack usage in {}: {}", room.room_id(), e);
+            }
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        let result = async {
+            let mut pack = if user_pack {
+                load_user_pack(&room.client()).await?
+            } else {
+                load_room_pack(&room, &pack_key).await?
+            }.unwrap_or_default();
+
+            match (action.as_str(), shortcode) {
+                ("create", _) => {
+                    let display_name = if pack_key.is_empty() { "WIP-Bot pack".to_string() } else { pack_key.clone() };
+                    pack.pack = Some(PackInfo {
+                        display_name: Some(display_name),
+                        usage,
+                        ..Default::default()
+                    });
+                }
+                ("add", Some(shortcode)) => {
+                    let Some(event_id) = in_reply_to else {
+                        anyhow::bail!("Please reply to an image to add it to the pack");
+                    };
+                    let mut image = fetch_pack_image(&room, &event_id).await?;
+                    image.usage = usage;
+                    pack.images.insert(shortcode, image);
+                }
+                ("remove", Some(shortcode)) => {
+                    if pack.images.remove(&shortcode).is_none() {
+                        anyhow::bail!("No image with shortcode {shortcode} in this pack");
+                    }
+                }
+                _ => anyhow::bail!("Usage: `!pack create|add|remove ...`"),
+            }
+
+            if user_pack {
+                room.client().account().set_account_data(UserImagePackContent { pack }).await?;
+            } else {
+                room.send_state_event_for_key(&pack_key, RoomImagePackContent { pack }).await?;
+            }
+            anyhow::Ok(())
+        }.await;
+
+        let content = match result {
+            Ok(_) => RoomMessageEventContent::notice_plain("Image pack updated"),
+            Err(e) => {
+                warn!("Failed to update image pack in {}: {}", room.room_id(), e);
+                RoomMessageEventContent::notice_plain(format!("Failed to update image pack: {e}"))
+            }
+        };
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send pack response in {}: {}", room.room_id(), e);
+        }
+    });
+}
+
+async fn handle_emote(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+) {
+    let (options, args): (Vec<&str>, Vec<&str>) = args.partition(|a| a.starts_with("--"));
+    let Some(shortcode) = args.first().map(|s| image_pack::normalize_shortcode(s)) else {
+        let content = RoomMessageEventContent::notice_plain("Usage: `!emote <shortcode>`");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send emote usage in {}: {}", room.room_id(), e);
+        }
+        return;
+    };
+    let inline = options.contains(&"--inline");
+    debug!("Got !emote {shortcode} in {} from {}, inline={inline}", room.room_id(), event.sender);
+
+    tokio::spawn(async move {
+        let mut packs: Vec<ImagePack> = room.get_state_events_static::<RoomImagePackContent>().await
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(room_pack_from_raw)
+            .collect();
+        match load_user_pack(&room.client()).await {
+            Ok(Some(pack)) => packs.push(pack),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to load user image pack: {e}"),
+        }
+
+        let Some(image) = packs.into_iter().find_map(|mut pack| pack.images.remove(&shortcode)) else {
+            let content = RoomMessageEventContent::notice_plain(format!("No image with shortcode {shortcode} found"));
+            if let Err(e) = room.send(content).await {
+                warn!("Failed to send emote error message in {}: {}", room.room_id(), e);
+            }
+            return;
+        };
+
+        let result = if inline {
+            let html = format!(
+                "<img data-mx-emoticon src=\"{}\" alt=\":{shortcode}:\" title=\":{shortcode}:\" height=\"32\">",
+                image.url,
+            );
+            room.send(RoomMessageEventContent::text_html(format!(":{shortcode}:"), html)).await
+        } else {
+            room.send(StickerEventContent::new(
+                image.body.unwrap_or(shortcode),
+                image.info.unwrap_or_else(ImageInfo::new),
+                image.url,
+            )).await
+        };
+        if let Err(e) = result {
+            warn!("Failed to send emote in {}: {}", room.room_id(), e);
+        }
+    });
+}
+
+async fn load_room_pack(room: &Room, key: &str) -> anyhow::Result<Option<ImagePack>> {
+    Ok(room.get_state_event_static_for_key::<RoomImagePackContent, str>(key).await?
+        .and_then(room_pack_from_raw))
+}
+
+fn room_pack_from_raw(raw: RawSyncOrStrippedState<RoomImagePackContent>) -> Option<ImagePack> {
+    let json = match raw {
+        RawSyncOrStrippedState::Sync(raw) => raw.into_json(),
+        RawSyncOrStrippedState::Stripped(raw) => raw.into_json(),
+    };
+    let event: serde_json::Value = serde_json::from_str(json.get()).ok()?;
+    serde_json::from_value(event["content"].clone()).ok()
+}
+
+async fn load_user_pack(client: &Client) -> anyhow::Result<Option<ImagePack>> {
+    Ok(client.account().account_data::<UserImagePackContent>().await?
+        .map(|raw| raw.deserialize())
+        .transpose()?
+        .map(|content| content.pack))
+}
+
+// Unencrypted image or sticker of the given event, as it would be stored in an image pack
+async fn fetch_pack_image(room: &Room, event_id: &OwnedEventId) -> anyhow::Result<PackImage> {
+    let event = room.event(event_id, None).await?
+        .into_raw()
+        .deserialize()?
+        .into_full_event(room.room_id().into());
+    let (body, source, info) = match event {
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(MessageLikeEvent::Original(message))) => {
+            match message.content.msgtype {
+                MessageType::Image(content) => (content.body, content.source, content.info.map(|i| *i)),
+                _ => anyhow::bail!("Not an image"),
+            }
+        }
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::Sticker(MessageLikeEvent::Original(sticker))) => {
+            let url = match sticker.content.source {
+                StickerMediaSource::Plain(uri) => uri,
+                _ => anyhow::bail!("Encrypted images can't be used in image packs"),
+            };
+            (sticker.content.body, MediaSource::Plain(url), Some(sticker.content.info))
+        }
+        _ => anyhow::bail!("Not an image"),
+    };
+    let MediaSource::Plain(url) = source else {
+        anyhow::bail!("Encrypted images can't be used in image packs");
+    };
+    Ok(PackImage {
+        url,
+        body: Some(body),
+        info,
+        usage: Vec::new(),
+    })
+}
+
+const BAD_MEDIA_KINDS: &[&str] = &[
+    "truncated",
+    "wrongmime",
+    "empty",
+    "bomb",
+    "badmxc",
+    "wrongthumb",
+    "badkey",
+    "badhash",
+    "badiv",
+];
+
+async fn handle_bad_media(
+    mut args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let trusted = is_user_trusted(&event.sender, context.config.clone());
+    if !trusted {
+        return;
+    }
+    let kind = args.next().unwrap_or_default().to_ascii_lowercase();
+    debug!("Got !badmedia {kind} in {} from {}", room.room_id(), event.sender);
+    let kinds: Vec<&str> = if kind == "all" {
+        BAD_MEDIA_KINDS.to_vec()
+    } else if let Some(kind) = BAD_MEDIA_KINDS.iter().find(|k| **k == kind) {
+        vec!(kind)
+    } else {
+        let content = RoomMessageEventContent::notice_markdown(format!(
+            "Please specify one of: `all`, `{}`",
+            BAD_MEDIA_KINDS.join("`, `"),
+        ));
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send bad media help in {}: {}", room.room_id(), e);
+        }
+        return;
+    };
+
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+
+    tokio::spawn(async move {
+        for kind in kinds {
+            if let Err(e) = send_bad_media(kind, &room, &media_client).await {
+                warn!("Failed to send bad media {kind} in {}: {}", room.room_id(), e);
+                let content = RoomMessageEventContent::notice_plain(format!("Failed to send {kind}: {e}"));
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send bad media error message in {}: {}", room.room_id(), e);
+                }
+            }
+        }
+    });
+}
+
+async fn send_bad_media(kind: &str, room: &Room, media_client: &Client) -> anyhow::Result<()> {
+    let width: usize = 150;
+    let height: usize = 150;
+    let background_color = format!("#{:06x}", rand::random::<u32>() % 0xffffff);
+    let png_encoding = ImageEncoding::default();
+    let text_image = |text: &str, encoding: &ImageEncoding| {
+        let text = text.to_string();
+        let background_color = background_color.clone();
+        let encoding = encoding.clone();
+        async move {
+            image_generator::create_text_image(Some(&text), &background_color, "#ffffff", width, height, 24.0, &encoding).await
+        }
+    };
+    let upload = |mimetype: &'static str, data: Vec<u8>| async move {
+        let mimetype: mime::Mime = mimetype.parse()?;
+        anyhow::Ok(media_client.media().upload(&mimetype, data, None).await?.content_uri)
+    };
+    let image_info = |mimetype: &str, size: usize, width: usize, height: usize| assign!(ImageInfo::new(), {
+        width: width.try_into().ok(),
+        height: height.try_into().ok(),
+        size: size.try_into().ok(),
+        mimetype: Some(mimetype.to_string()),
+    });
+
+    let mut messages = Vec::new();
+    match kind {
+        "truncated" => {
+            let image = bad_media::truncate(&text_image("Truncated", &png_encoding).await?, 0.5);
+            let info = image_info("image/png", image.len(), width, height);
+            let uri = upload("image/png", image).await?;
+            messages.push(ImageMessageEventContent::plain("truncated.png".to_string(), uri).info(Some(Box::new(info))));
+        }
+        "wrongmime" => {
+            let jpeg_encoding = assign!(ImageEncoding::default(), { format: ImageFormat::Jpeg });
+            let image = text_image("JPEG as PNG", &jpeg_encoding).await?;
+            let info = image_info("image/png", image.len(), width, height);
+            let uri = upload("image/png", image).await?;
+            messages.push(ImageMessageEventContent::plain("actually-a-jpeg.png".to_string(), uri).info(Some(Box::new(info))));
+        }
+        "empty" => {
+            let info = image_info("image/png", 0, width, height);
+            let uri = upload("image/png", Vec::new()).await?;
+            messages.push(ImageMessageEventContent::plain("empty.png".to_string(), uri).info(Some(Box::new(info))));
+        }
+        "bomb" => {
+            let bomb_size = 16384;
+            let image = bad_media::create_png_bomb(bomb_size as u32, bomb_size as u32);
+            let info = image_info("image/png", image.len(), bomb_size, bomb_size);
+            let uri = upload("image/png", image).await?;
+            messages.push(ImageMessageEventContent::plain("bomb.png".to_string(), uri).info(Some(Box::new(info))));
+        }
+        "badmxc" => {
+            for uri in [
+                "",
+                "mxc://",
+                "mxc://example.com",
+                "mxc://example.com/",
+                "mxc://example.com/doesnotexist",
+                "mxc://not a server/media",
+                "https://example.com/image.png",
+                "not a uri",
+            ] {
+                let info = image_info("image/png", 1024, width, height);
+                messages.push(ImageMessageEventContent::plain(format!("Invalid mxc: {uri}"), OwnedMxcUri::from(uri)).info(Some(Box::new(info))));
+            }
+        }
+        "wrongthumb" => {
+            let image = text_image("Image", &png_encoding).await?;
+            let info = image_info("image/png", image.len(), width, height);
+            let uri = upload("image/png", image).await?;
+            let thumbnail = image_generator::create_text_image(
+                Some(&"Thumbnail\n≠\nimage".to_string()),
+                &format!("#{:06x}", rand::random::<u32>() % 0xffffff),
+                "#000000",
+                width * 2,
+                height,
+                24.0,
+                &png_encoding,
+            ).await?;
+            let thumbnail_info = assign!(ThumbnailInfo::new(), {
+                width: (width * 2).try_into().ok(),
+                height: height.try_into().ok(),
+                size: thumbnail.len().try_into().ok(),
+                mimetype: Some("image/png".to_string()),
+            });
+            let thumbnail_uri = upload("image/png", thumbnail).await?;
+            let info = assign!(info, {
+                thumbnail_info: Some(Box::new(thumbnail_info)),
+                thumbnail_source: Some(MediaSource::Plain(thumbnail_uri)),
+            });
+            messages.push(ImageMessageEventContent::plain("wrong-thumbnail.png".to_string(), uri).info(Some(Box::new(info))));
+        }
+        "badkey" | "badhash" | "badiv" => {
+            let image = text_image(&format!("Encrypted with {kind}"), &png_encoding).await?;
+            let info = image_info("image/png", image.len(), width, height);
+            let mut reader = std::io::Cursor::new(image);
+            let mut file = media_client.upload_encrypted_file(&mut reader).await?;
+            match kind {
+                "badkey" => file.key.k = Base64::new(file_generator::create_random(32)),
+                "badhash" => {
+                    file.hashes.insert("sha256".to_string(), Base64::new(file_generator::create_random(32)));
+                }
+                _ => file.iv = Base64::new(file_generator::create_random(16)),
+            }
+            messages.push(ImageMessageEventContent::encrypted(format!("{kind}.png"), file).info(Some(Box::new(info))));
+        }
+        _ => anyhow::bail!("Unknown bad media kind {kind}"),
+    }
+
+    for message in messages {
+        room.send(RoomMessageEventContent::new(MessageType::Image(message))).await?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, Default)]
+struct TtsRequest {
+    voice: Option<String>,
+    speaker: Option<i64>,
+    speed: Option<f32>,
+    // Plain WAV instead of a voice message
+    as_file: bool,
+    // Upload even if we uploaded the same audio before
+    fresh: bool,
+}
+
+async fn handle_tts(
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let config = context.config;
+    let vip = is_user_vip(&event.sender, config.clone());
+    let trusted = is_user_trusted_not_vip(&event.sender, config.clone());
+    debug!("Got !tts in {} from {}, vip={vip}, trusted={trusted}", room.room_id(), event.sender);
+    if !vip && !trusted {
+        return;
+    }
+
+    let Some(tts) = context.tts else {
+        error!("No TTS voices configured");
+        return;
+    };
+
+    let (options, text) = leading_options(command_text(event.content.body(), &args));
+    let option_value = |prefix: &str| options.iter().find_map(|o| o.strip_prefix(prefix));
+    let request = TtsRequest {
+        voice: option_value("--voice=").map(|v| v.to_string()),
+        speaker: option_value("--speaker=").and_then(|s| s.parse::<i64>().ok()),
+        speed: option_value("--speed=").and_then(|s| s.parse::<f32>().ok()),
+        as_file: options.contains(&"--file"),
+        fresh: options.contains(&"--fresh"),
+    };
+
+    if options.contains(&"--voices") {
+        let content = RoomMessageEventContent::notice_plain(format!("Available voices: {}", tts.voice_names().join(", ")));
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send voice list in {}: {}", room.room_id(), e);
+        }
+        return;
+    }
+
+    let text = text.to_string();
+    let in_reply_to = if let Some(Relation::Reply { in_reply_to }) = &event.content.relates_to {
+        Some(in_reply_to.event_id.clone())
+    } else {
+        None
+    };
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache;
+
+    tokio::spawn(async move {
+        // Without text of its own, read the replied-to message aloud
+        let text = match in_reply_to {
+            Some(event_id) if text.is_empty() => match fetch_message(&room, &event_id).await {
+                Ok(content) => speakable_text(&content),
+                Err(e) => {
+                    warn!("Failed to look up replied-to event for TTS in {}: {}", room.room_id(), e);
+                    let content = RoomMessageEventContent::notice_plain("Failed to look-up replied-to event");
+                    if let Err(e) = room.send(content).await {
+                        warn!("Failed to send TTS error message in {}: {}", room.room_id(), e);
+                    }
+                    return;
+                }
+            },
+            _ if text.is_empty() => "Nope".to_string(),
+            _ => text,
+        };
+        let text = limit_tts_text(text, &config);
+        send_tts(&room, &media_client, &upload_cache, tts, text, request).await;
+    });
+}
+
+// Synthesis takes time and memory proportional to the text, so only read the start of long texts
+fn limit_tts_text(text: String, config: &Config) -> String {
+    let max_chars = config.get::<usize>("bot.tts.max_chars").unwrap_or(1000);
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => text[..end].to_string(),
+        None => text,
+    }
+}
+
+async fn send_tts(
+    room: &Room,
+    media_client: &Client,
+    upload_cache: &UploadCache,
+    tts: Arc<Tts>,
+    text: String,
+    request: TtsRequest,
+) {
+    let synthesis = {
+        let request = request.clone();
+        tokio::task::spawn_blocking(move || {
+            tts.synthesize(&text, request.voice.as_deref(), request.speaker, request.speed)
+        }).await
+    };
+    let audio = match synthesis {
+        Ok(Ok(audio)) => audio,
+        Ok(Err(e)) => {
+            error!("Failed to TTS: {e}");
+            let content = RoomMessageEventContent::notice_plain(format!("Failed to TTS: {e}"));
+            if let Err(e) = room.send(content).await {
+                warn!("Failed to send TTS error message in {}: {}", room.room_id(), e);
+            }
+            return;
+        }
+        Err(e) => {
+            error!("TTS task failed: {e}");
+            return;
+        }
+    };
+
+    debug!("TTS generated");
+
+    let target = UploadTarget { media_client, upload_cache, fresh: request.fresh };
+    if request.as_file {
+        send_audio_file(room, &target, audio.to_wav(), "audio/wav", "tts.wav", audio.duration()).await;
+    } else {
+        send_voice_message(room, &target, audio, Application::Voip, "tts.ogg").await;
+    }
+}
+
+async fn handle_auto_read_toggle(
+    mut args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let trusted = is_user_trusted(&event.sender, context.config.clone());
+    if !trusted {
+        return;
+    }
+    let target = args.next().unwrap_or("me");
+    debug!("Got !autoread {target} in {} from {}", room.room_id(), event.sender);
+
+    let response = if target == "off" {
+        context.auto_read.lock().expect("Auto-read lock poisoned").remove(room.room_id());
+        "Auto-read disabled".to_string()
+    } else {
+        let user_id = if target == "me" {
+            Ok(event.sender.clone())
+        } else {
+            OwnedUserId::try_from(target)
+        };
+        match user_id {
+            Ok(user_id) if context.tts.is_some() => {
+                let response = format!("Reading all messages by {user_id} aloud");
+                context.auto_read.lock().expect("Auto-read lock poisoned").insert(room.room_id().to_owned(), user_id);
+                response
+            }
+            Ok(_) => "No TTS voices configured".to_string(),
+            Err(e) => format!("Invalid user ID: {e}"),
+        }
+    };
+    let content = RoomMessageEventContent::notice_plain(response);
+    if let Err(e) = room.send(content).await {
+        warn!("Failed to send auto-read response in {}: {}", room.room_id(), e);
+    }
+}
+
+// Voice messages of the user chosen with !autoread, for messages that are not commands.
+// Returns whether the message was read aloud.
+pub async fn handle_auto_read(
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    context: &WipContext,
+) -> bool {
+    let is_auto_read = context.auto_read.lock()
+        .expect("Auto-read lock poisoned")
+        .get(room.room_id())
+        .is_some_and(|user_id| *user_id == event.sender);
+    let Some(tts) = context.tts.clone().filter(|_| is_auto_read) else {
+        return false;
+    };
+    debug!("Auto-reading {} in {}", event.event_id, room.room_id());
+
+    let text = limit_tts_text(speakable_text(&event.content), &context.config);
+    let room = room.clone();
+    let media_client = context.media_client.clone().unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache.clone();
+    tokio::spawn(async move {
+        send_tts(&room, &media_client, &upload_cache, tts, text, TtsRequest::default()).await;
+    });
+    true
+}
+
+async fn handle_transcribe(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let vip = is_user_vip(&event.sender, context.config.clone());
+    let trusted = is_user_trusted_not_vip(&event.sender, context.config.clone());
+    debug!("Got !transcribe in {} from {}, vip={vip}, trusted={trusted}", room.room_id(), event.sender);
+    if !vip && !trusted {
+        return;
+    }
+
+    let Some(transcriber) = context.transcriber else {
+        error!("No transcription model configured");
+        return;
+    };
+
+    let event_id = if let Some(Relation::Reply { in_reply_to }) = event.content.relates_to {
+        in_reply_to.event_id
+    } else {
+        let content =
+            RoomMessageEventContent::notice_plain("Please reply to a voice message while issuing the !transcribe command");
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send error message in {}: {}", room.room_id(), e);
+        }
+        return;
+    };
+
+    let max_size = context.config.get::<usize>("bot.transcribe.max_size").unwrap_or(25_000_000);
+    let max_duration = Duration::from_secs(context.config.get::<u64>("bot.transcribe.max_duration").unwrap_or(300));
 
+    tokio::spawn(async move {
+        let message = match transcribe_message(&room, &event_id, transcriber, max_size, max_duration).await {
+            Ok((audio_event, transcript)) => {
+                let transcript = if transcript.is_empty() { "(No speech detected)".to_string() } else { transcript };
+                RoomMessageEventContent::notice_plain(transcript).make_for_thread(
+                    &audio_event,
+                    ReplyWithinThread::No,
+                    AddMentions::No,
+                )
+            }
+            Err(e) => {
+                warn!("Failed to transcribe {} in {}: {}", event_id, room.room_id(), e);
+                RoomMessageEventContent::notice_plain(format!("Failed to transcribe: {e}"))
+            }
+        };
         if let Err(e) = room.send(message).await {
-            warn!("Failed to send audio in {}: {}", room.room_id(), e);
+            warn!("Failed to send transcript in {}: {}", room.room_id(), e);
+        }
+    });
+}
+
+// Download and decrypt the audio of the given event, then transcribe it
+async fn transcribe_message(
+    room: &Room,
+    event_id: &OwnedEventId,
+    transcriber: Arc<Transcriber>,
+    max_size: usize,
+    max_duration: Duration,
+) -> anyhow::Result<(OriginalRoomMessageEvent, String)> {
+    let audio_event = fetch_message_event(room, event_id).await?;
+    let (source, claimed_size) = match &audio_event.content.msgtype {
+        MessageType::Audio(content) => (content.source.clone(), content.info.as_ref().and_then(|info| info.size)),
+        MessageType::File(content) if content.info.as_ref()
+            .and_then(|info| info.mimetype.as_deref())
+            .is_some_and(|mimetype| mimetype.starts_with("audio/")) =>
+            (content.source.clone(), content.info.as_ref().and_then(|info| info.size)),
+        _ => anyhow::bail!("Not an audio message"),
+    };
+    // The claimed size saves us the download, but only the actual data can be trusted
+    if claimed_size.is_some_and(|size| u64::from(size) > max_size as u64) {
+        anyhow::bail!("Audio is larger than {max_size} bytes");
+    }
+    let request = MediaRequestParameters { source, format: MediaFormat::File };
+    let data = room.client().media().get_media_content(&request, true).await?;
+    if data.len() > max_size {
+        anyhow::bail!("Audio is larger than {max_size} bytes");
+    }
+
+    let transcript = tokio::task::spawn_blocking(move || {
+        let audio = audio::decode(&data)?;
+        if audio.duration() > max_duration {
+            anyhow::bail!("Audio is longer than {} seconds", max_duration.as_secs());
+        }
+        transcriber.transcribe(&audio)
+    }).await??;
+    Ok((audio_event, transcript))
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SyntheticAudio {
+    Tone,
+    Noise,
+    Silence,
+}
+
+async fn handle_synthetic_audio(
+    kind: SyntheticAudio,
+    args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let config = context.config;
+    let trusted = is_user_trusted(&event.sender, config.clone());
+    if !trusted {
+        return;
+    }
+    let (options, args): (Vec<&str>, Vec<&str>) = args.partition(|a| a.starts_with("--"));
+    let mut args = args.into_iter();
+    let option_value = |prefix: &str| options.iter().find_map(|o| o.strip_prefix(prefix));
+
+    let max_duration = config.get::<f32>("bot.audio.max_duration").unwrap_or(60.0);
+    let frequency = match kind {
+        SyntheticAudio::Tone => args.next().unwrap_or_default().parse::<f32>().unwrap_or(440.0).clamp(1.0, 24_000.0),
+        _ => 0.0,
+    };
+    let seconds = args.next().unwrap_or_default().parse::<f32>().unwrap_or(3.0).clamp(0.0, max_duration);
+    let as_voice = options.contains(&"--voice");
+    let fresh = options.contains(&"--fresh");
+    let format = if as_voice { "ogg" } else { option_value("--format=").unwrap_or("ogg") }.to_ascii_lowercase();
+    debug!("Got synthetic audio {kind:?} {frequency}Hz {seconds}s {format} in {} from {}, voice={as_voice}", room.room_id(), event.sender);
+
+    let stereo = options.contains(&"--stereo");
+    let sweep_to = option_value("--sweep=")
+        .and_then(|f| f.parse::<f32>().ok())
+        .map(|f| f.clamp(1.0, 24_000.0));
+    let generate = move || match kind {
+        SyntheticAudio::Tone if stereo => audio_generator::channel_test(frequency, seconds),
+        SyntheticAudio::Tone => match sweep_to {
+            Some(to_frequency) => audio_generator::sweep(frequency, to_frequency, seconds),
+            None => audio_generator::sine(frequency, seconds),
+        },
+        SyntheticAudio::Noise => audio_generator::white_noise(seconds),
+        SyntheticAudio::Silence => audio_generator::silence(seconds),
+    };
+    let name = match kind {
+        SyntheticAudio::Tone => "tone",
+        SyntheticAudio::Noise => "noise",
+        SyntheticAudio::Silence => "silence",
+    };
+
+    let media_client = context.media_client.unwrap_or_else(|| room.client());
+    let upload_cache = context.upload_cache;
+
+    tokio::spawn(async move {
+        let target = UploadTarget { media_client: &media_client, upload_cache: &upload_cache, fresh };
+        // Up to bot.audio.max_duration seconds of samples, so keep it off the async runtime
+        let audio = match tokio::task::spawn_blocking(generate).await {
+            Ok(audio) => audio,
+            Err(e) => {
+                error!("Audio generation task failed: {e}");
+                return;
+            }
+        };
+        if as_voice {
+            send_voice_message(&room, &target, audio, Application::Audio, &format!("{name}.ogg")).await;
+            return;
+        }
+        let (mimetype, extension) = match format.as_str() {
+            "wav" => ("audio/wav", "wav"),
+            "mp3" => ("audio/mpeg", "mp3"),
+            _ => ("audio/ogg", "ogg"),
+        };
+        let duration = audio.duration();
+        let data = tokio::task::spawn_blocking(move || match extension {
+            "wav" => Ok(audio.to_wav()),
+            "mp3" => audio.to_mp3(),
+            _ => audio.to_ogg_opus(Application::Audio),
+        }).await;
+        let data = match data {
+            Ok(Ok(data)) => data,
+            Ok(Err(e)) => {
+                error!("Failed to encode {name} as {format}: {e}");
+                let content = RoomMessageEventContent::notice_plain(format!("Failed to encode audio: {e}"));
+                if let Err(e) = room.send(content).await {
+                    warn!("Failed to send audio error message in {}: {}", room.room_id(), e);
+                }
+                return;
+            }
+            Err(e) => {
+                error!("Audio encoding task failed: {e}");
+                return;
+            }
+        };
+        send_audio_file(&room, &target, data, mimetype, &format!("{name}.{extension}"), duration).await;
+    });
+}
+
+// Number of amplitudes in voice message waveforms
+const VOICE_WAVEFORM_BARS: usize = 100;
+
+// Where generated media gets uploaded to
+struct UploadTarget<'a> {
+    media_client: &'a Client,
+    upload_cache: &'a UploadCache,
+    // Upload again even if the same content was uploaded before
+    fresh: bool,
+}
+
+impl UploadTarget<'_> {
+    async fn upload(&self, mimetype: &str, data: Vec<u8>) -> anyhow::Result<OwnedMxcUri> {
+        let mimetype: mime::Mime = mimetype.parse().expect("Valid mimetype");
+        self.upload_cache.upload(self.media_client, &mimetype, data, self.fresh).await
+    }
+}
+
+// Ogg/Opus audio with waveform and the MSC3245 voice marker, so clients render it as voice message
+async fn send_voice_message(
+    room: &Room,
+    target: &UploadTarget<'_>,
+    audio: audio::Audio,
+    application: Application,
+    body: &str,
+) {
+    let duration = audio.duration().as_millis() as u64;
+    let encoded = tokio::task::spawn_blocking(move || {
+        audio.to_ogg_opus(application).map(|ogg| (ogg, audio.waveform(VOICE_WAVEFORM_BARS)))
+    }).await;
+    let (ogg, waveform) = match encoded {
+        Ok(Ok(encoded)) => encoded,
+        Ok(Err(e)) => {
+            error!("Failed to encode voice message: {e}");
             return;
         }
+        Err(e) => {
+            error!("Voice message encoding task failed: {e}");
+            return;
+        }
+    };
+    let size = ogg.len();
+    let mimetype = "audio/ogg";
+    let uri = match target.upload(mimetype, ogg).await {
+        Ok(mxc) => mxc,
+        Err(e) => {
+            error!("Failed to upload voice message: {}", e);
+            return
+        }
+    };
+
+    let content = serde_json::json!({
+        "msgtype": "m.audio",
+        "body": body,
+        "url": uri,
+        "info": {
+            "mimetype": mimetype,
+            "size": size,
+            "duration": duration,
+        },
+        "org.matrix.msc1767.text": body,
+        "org.matrix.msc1767.file": {
+            "url": uri,
+            "name": body,
+            "mimetype": mimetype,
+            "size": size,
+        },
+        "org.matrix.msc1767.audio": {
+            "duration": duration,
+            "waveform": waveform,
+        },
+        "org.matrix.msc3245.voice": {},
+    });
+    if let Err(e) = room.send_raw("m.room.message", content).await {
+        warn!("Failed to send voice message in {}: {}", room.room_id(), e);
+        target.upload_cache.forget(&uri).await;
+    }
+}
+
+async fn send_audio_file(
+    room: &Room,
+    target: &UploadTarget<'_>,
+    data: Vec<u8>,
+    mimetype: &str,
+    body: &str,
+    duration: Duration,
+) {
+    let size = data.len();
+    let uri = match target.upload(mimetype, data).await {
+        Ok(mxc) => mxc,
+        Err(e) => {
+            error!("Failed to upload audio: {}", e);
+            return
+        }
+    };
+
+    let audio_info = assign!(AudioInfo::new(), {
+        duration: Some(duration),
+        mimetype: Some(mimetype.to_string()),
+        size: size.try_into().ok(),
+    });
+    let audio_content = AudioMessageEventContent::plain(
+        body.to_string(),
+        uri.clone(),
+    ).info(Some(Box::new(audio_info)));
+    if let Err(e) = room.send(RoomMessageEventContent::new(MessageType::Audio(audio_content))).await {
+        warn!("Failed to send audio in {}: {}", room.room_id(), e);
+        target.upload_cache.forget(&uri).await;
+    }
+}
+
+async fn handle_format(
+    mut args: SplitWhitespace<'_>,
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    context: WipContext,
+) {
+    let trusted = is_user_trusted(&event.sender, context.config.clone());
+    if !trusted {
+        return;
+    }
+    let case = args.next().unwrap_or_default().to_lowercase();
+    debug!("Got !format {case} in {} from {}", room.room_id(), event.sender);
+
+    let samples: Vec<_> = FORMAT_SAMPLES.iter().filter(|sample| case == "all" || sample.name == case).collect();
+    if samples.is_empty() {
+        let names: Vec<&str> = FORMAT_SAMPLES.iter().map(|sample| sample.name).collect();
+        let content = RoomMessageEventContent::notice_plain(format!("Usage: `!format <case|all>`, cases: {}", names.join(", ")));
+        if let Err(e) = room.send(content).await {
+            warn!("Failed to send format usage in {}: {}", room.room_id(), e);
+        }
+        return;
+    }
+
+    let mxc = context.stickers[0].mxc.to_string();
+    tokio::spawn(async move {
+        for sample in samples {
+            let html = sample.html.replace("{mxc}", &mxc);
+            let content = RoomMessageEventContent::text_html(sample.plain, html);
+            if let Err(e) = room.send(content).await {
+                warn!("Failed to send format sample {} in {}: {}", sample.name, room.room_id(), e);
+                return;
+            }
+        }
     });
 }
 
@@ -988,3 +3720,44 @@ async fn handle_invite(
         }
     });
 }
+
+// Raw text of the arguments, keeping quotes and line breaks. `args` are what the dispatcher left
+// over after the optional mention and the command, so we skip as many words from the start.
+fn command_text<'a>(body: &'a str, args: &SplitWhitespace<'_>) -> &'a str {
+    let skip = body.split_whitespace().count().saturating_sub(args.clone().count());
+    let mut text = body.trim_start();
+    for _ in 0..skip {
+        text = text[text.find(char::is_whitespace).unwrap_or(text.len())..].trim_start();
+    }
+    text.trim_end()
+}
+
+// Split a message body into arguments, keeping "quoted text" together
+fn split_quoted_args(body: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+    for c in body.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    result.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_arg = true;
+            }
+        }
+    }
+    if has_arg {
+        result.push(current);
+    }
+    result
+}
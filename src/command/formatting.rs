@@ -0,0 +1,81 @@
+// Formatted bodies for !format, to check how clients render the HTML subset of the spec.
+// `{mxc}` is replaced with one of the bot's sticker mxcs.
+pub struct FormatSample {
+    pub name: &'static str,
+    pub plain: &'static str,
+    pub html: &'static str,
+}
+
+pub const FORMAT_SAMPLES: &[FormatSample] = &[
+    FormatSample {
+        name: "inline",
+        plain: "**Bold**, *italic*, __underlined__, ~~struck~~, `code`, H~2~O, E=mc^2^ and a link: https://matrix.org",
+        html: "<b>Bold</b>, <i>italic</i>, <u>underlined</u>, <del>struck</del>, <code>code</code>, \
+               H<sub>2</sub>O, E=mc<sup>2</sup> and a <a href=\"https://matrix.org\">link</a>",
+    },
+    FormatSample {
+        name: "headers",
+        plain: "# Header 1\n## Header 2\n### Header 3\n#### Header 4\n##### Header 5\n###### Header 6\nText",
+        html: "<h1>Header 1</h1><h2>Header 2</h2><h3>Header 3</h3><h4>Header 4</h4><h5>Header 5</h5><h6>Header 6</h6>\
+               <p>Text</p>",
+    },
+    FormatSample {
+        name: "lists",
+        plain: "- Spam\n  1. Eggs\n  2. Bacon\n     - Spam\n       - More spam\n- Beans\n\n3. Starting at three\n4. Four",
+        html: "<ul><li>Spam<ol><li>Eggs</li><li>Bacon<ul><li>Spam<ul><li>More spam</li></ul></li></ul></li></ol></li>\
+               <li>Beans</li></ul><ol start=\"3\"><li>Starting at three</li><li>Four</li></ol>",
+    },
+    FormatSample {
+        name: "table",
+        plain: "| Dish | Spam | Price |\n|---|:---:|---:|\n| Egg and spam | 1 | 2.50 |\n| Spam, spam, spam, egg and spam | 4 | 4.20 |",
+        html: "<table><thead><tr><th>Dish</th><th align=\"center\">Spam</th><th align=\"right\">Price</th></tr></thead>\
+               <tbody><tr><td>Egg and spam</td><td align=\"center\">1</td><td align=\"right\">2.50</td></tr>\
+               <tr><td>Spam, spam, spam, <b>egg</b> and spam</td><td align=\"center\">4</td><td align=\"right\">4.20</td></tr>\
+               </tbody></table>",
+    },
+    FormatSample {
+        name: "code",
+        plain: "```rust\nfn main() {\n    println!(\"Spam\");\n}\n```\n```python\nprint(\"Spam\" * 3)\n```\n```\nNo language <b>not bold</b>\n```",
+        html: "<pre><code class=\"language-rust\">fn main() {\n    println!(&quot;Spam&quot;);\n}\n</code></pre>\
+               <pre><code class=\"language-python\">print(&quot;Spam&quot; * 3)\n</code></pre>\
+               <pre><code>No language &lt;b&gt;not bold&lt;/b&gt;\n</code></pre>",
+    },
+    FormatSample {
+        name: "quote",
+        plain: "> Spam\n> > Nested spam\n> > > Even more nested spam\n\nNot quoted",
+        html: "<blockquote><p>Spam</p><blockquote><p>Nested spam</p><blockquote><p>Even more nested spam</p>\
+               </blockquote></blockquote></blockquote><p>Not quoted</p>",
+    },
+    FormatSample {
+        name: "spoiler",
+        plain: "Spoiler: [Spoiler](It's all spam) and [Spoiler: food](spam again)",
+        html: "Spoiler: <span data-mx-spoiler>It's all spam</span> and \
+               <span data-mx-spoiler=\"food\">spam again</span>",
+    },
+    FormatSample {
+        name: "color",
+        plain: "Red, green on black, blue with legacy font tag",
+        html: "<span data-mx-color=\"#ff0000\">Red</span>, \
+               <span data-mx-color=\"#00ff00\" data-mx-bg-color=\"#000000\">green on black</span>, \
+               <font color=\"#0000ff\">blue with legacy font tag</font>",
+    },
+    FormatSample {
+        name: "math",
+        plain: "Inline $e^{i\\pi} + 1 = 0$ and block:\n$$\n\\sum_{n=1}^{\\infty} \\frac{1}{n^2} = \\frac{\\pi^2}{6}\n$$",
+        html: "Inline <span data-mx-maths=\"e^{i\\pi} + 1 = 0\"><code>e^{i\\pi} + 1 = 0</code></span> and block:\
+               <div data-mx-maths=\"\\sum_{n=1}^{\\infty} \\frac{1}{n^2} = \\frac{\\pi^2}{6}\">\
+               <pre><code>\\sum_{n=1}^{\\infty} \\frac{1}{n^2} = \\frac{\\pi^2}{6}</code></pre></div>",
+    },
+    FormatSample {
+        name: "image",
+        plain: "Inline emoji :spam: and a larger image: Spam",
+        html: "Inline emoji <img data-mx-emoticon src=\"{mxc}\" alt=\":spam:\" title=\":spam:\" height=\"32\"> \
+               and a larger image: <img src=\"{mxc}\" alt=\"Spam\" title=\"Spam\" width=\"128\" height=\"128\">",
+    },
+    FormatSample {
+        name: "misc",
+        plain: "Line\nbreak\n\n---\n\nText after a rule, with <angle brackets> & ampersands",
+        html: "Line<br>break<hr><p>Text after a rule, with &lt;angle brackets&gt; &amp; ampersands</p>\
+               <details><summary>Details</summary>Hidden spam</details>",
+    },
+];
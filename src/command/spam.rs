@@ -22,8 +22,23 @@ pub const TEXT_SPAM: &'static [&str] = &[
     "Spaaam!"
 ];
 
-pub const STICKER_SPAM: &'static [&str] = &[
-    "mxc://local.beeper.com/imjoshin_slS22Mgs4Vnp8cj8D0sPcU6Yniu01mqagZRkdG9Ue6a48B1sOzO7qFxk3ZhpwrJ3",
-    "mxc://local.beeper.com/imjoshin_dFbm02esvz1eosgPF7ZTzWk4DunfU9xTcIsLr49qNiXHRypTiPFmq4lKBYmjKCiZ",
-    "mxc://local.beeper.com/spiritcroc_TnTnE9q1fOXD0gtA9UlcBrStvCVLWwAbAtKcAIOKqWJRtQeZwsniGSRVA8xV51tB",
+pub const POLL_ANSWERS: &'static [&str] = &[
+    "Spam",
+    "Lovely Spam!",
+    "Wonderful Spam!",
+    "Magnificent Spam!",
+    "Surgical Spam!",
+];
+
+pub const REACTION_EMOJI: &'static [&str] = &[
+    "👍",
+    "❤️",
+    "😂",
+    "🐢",
+    "🥫",
+    "🏳️‍🌈",
+    "👩🏽‍💻",
+    "🇩🇪",
+    "1️⃣",
+    "🫠",
 ];
@@ -0,0 +1,177 @@
+use rand::RngCore;
+
+const TEXT_LINE: &str = "Spam, Spam, Spam, Lovely Spam! Wonderful Spam!\n";
+
+// Parse sizes like "512", "10k" or "2M"
+pub fn parse_size(size: &str) -> Option<usize> {
+    let (number, factor) = match size.chars().last()?.to_ascii_lowercase() {
+        'k' => (&size[..size.len()-1], 1_000),
+        'm' => (&size[..size.len()-1], 1_000_000),
+        'g' => (&size[..size.len()-1], 1_000_000_000),
+        _ => (size, 1),
+    };
+    number.parse::<usize>().ok().map(|n| n.saturating_mul(factor))
+}
+
+// Default file extension for generated content of the given mimetype
+pub fn extension_for(mimetype: &str) -> &'static str {
+    match mimetype {
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "text/html" => "html",
+        "text/markdown" => "md",
+        "application/json" => "json",
+        m if m.starts_with("text/") => "txt",
+        _ => "bin",
+    }
+}
+
+// Generate content of the requested size that is valid for the mimetype where we know how,
+// or random bytes otherwise. Formats with a minimum size may end up larger than requested.
+pub fn create_file(mimetype: &str, size: usize) -> Vec<u8> {
+    match mimetype {
+        "application/pdf" => create_pdf(size),
+        "application/zip" => create_zip(size),
+        m if m.starts_with("text/") || m == "application/json" => create_text(size),
+        _ => create_random(size),
+    }
+}
+
+pub fn create_random(size: usize) -> Vec<u8> {
+    let mut data = vec![0u8; size];
+    rand::rng().fill_bytes(&mut data);
+    data
+}
+
+pub fn create_text(size: usize) -> Vec<u8> {
+    TEXT_LINE.bytes().cycle().take(size).collect()
+}
+
+pub fn create_pdf(size: usize) -> Vec<u8> {
+    let text = "Hello from the WIP-Bot";
+    let stream = format!("BT /F1 24 Tf 20 70 Td ({text}) Tj ET");
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 400 144] /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> >> >>".to_string(),
+        format!("<< /Length {} >>\nstream\n{stream}\nendstream", stream.len()),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+    ];
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{object}\nendobj\n", i+1).as_bytes());
+    }
+
+    let trailer = |xref_offset: usize| {
+        let entries: String = offsets.iter().map(|o| format!("{o:010} 00000 n \n")).collect();
+        format!(
+            "xref\n0 {}\n0000000000 65535 f \n{entries}trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n",
+            objects.len() + 1,
+            objects.len() + 1,
+        )
+    };
+
+    // Pad with comments before the xref table, which moves the xref offset with it. The trailer
+    // only grows with the digit count of that offset, so this settles after the second round.
+    let mut padding = 0;
+    loop {
+        let needed = size.saturating_sub(pdf.len() + trailer(pdf.len() + padding).len());
+        if needed <= padding {
+            break;
+        }
+        padding = needed;
+    }
+    pad_with_comments(&mut pdf, padding);
+    let xref_offset = pdf.len();
+    pdf.extend_from_slice(trailer(xref_offset).as_bytes());
+    pdf
+}
+
+fn pad_with_comments(pdf: &mut Vec<u8>, mut padding: usize) {
+    while padding > 0 {
+        let line = padding.min(80);
+        if line > 1 {
+            pdf.push(b'%');
+            pdf.extend(std::iter::repeat_n(b'x', line - 2));
+        }
+        pdf.push(b'\n');
+        padding -= line;
+    }
+}
+
+// Without zip64, sizes and offsets have to fit into 32 bits
+const ZIP_MAX_SIZE: usize = u32::MAX as usize;
+
+// A zip archive with a single uncompressed text file, at most 4 GiB
+pub fn create_zip(size: usize) -> Vec<u8> {
+    let name = b"spam.txt";
+    let overhead = 30 + 46 + 22 + 2 * name.len();
+    let data = create_text(size.min(ZIP_MAX_SIZE).saturating_sub(overhead));
+    let crc = crc32(&data);
+    let data_len = data.len() as u32;
+    let name_len = name.len() as u16;
+
+    let mut zip = Vec::with_capacity(overhead + data.len());
+    // Local file header
+    zip.extend_from_slice(&0x04034b50u32.to_le_bytes());
+    zip.extend_from_slice(&20u16.to_le_bytes()); // version needed
+    zip.extend_from_slice(&0u16.to_le_bytes()); // flags
+    zip.extend_from_slice(&0u16.to_le_bytes()); // stored
+    zip.extend_from_slice(&0u16.to_le_bytes()); // time
+    zip.extend_from_slice(&0x21u16.to_le_bytes()); // date: 1980-01-01
+    zip.extend_from_slice(&crc.to_le_bytes());
+    zip.extend_from_slice(&data_len.to_le_bytes());
+    zip.extend_from_slice(&data_len.to_le_bytes());
+    zip.extend_from_slice(&name_len.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes()); // extra length
+    zip.extend_from_slice(name);
+    zip.extend_from_slice(&data);
+
+    // Central directory
+    let central_directory_offset = zip.len() as u32;
+    zip.extend_from_slice(&0x02014b50u32.to_le_bytes());
+    zip.extend_from_slice(&20u16.to_le_bytes()); // version made by
+    zip.extend_from_slice(&20u16.to_le_bytes()); // version needed
+    zip.extend_from_slice(&0u16.to_le_bytes()); // flags
+    zip.extend_from_slice(&0u16.to_le_bytes()); // stored
+    zip.extend_from_slice(&0u16.to_le_bytes()); // time
+    zip.extend_from_slice(&0x21u16.to_le_bytes()); // date
+    zip.extend_from_slice(&crc.to_le_bytes());
+    zip.extend_from_slice(&data_len.to_le_bytes());
+    zip.extend_from_slice(&data_len.to_le_bytes());
+    zip.extend_from_slice(&name_len.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes()); // extra length
+    zip.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    zip.extend_from_slice(&0u16.to_le_bytes()); // disk number
+    zip.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+    zip.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+    zip.extend_from_slice(&0u32.to_le_bytes()); // local header offset
+    zip.extend_from_slice(name);
+    let central_directory_size = zip.len() as u32 - central_directory_offset;
+
+    // End of central directory
+    zip.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes()); // disk number
+    zip.extend_from_slice(&0u16.to_le_bytes()); // central directory disk
+    zip.extend_from_slice(&1u16.to_le_bytes()); // entries on disk
+    zip.extend_from_slice(&1u16.to_le_bytes()); // entries total
+    zip.extend_from_slice(&central_directory_size.to_le_bytes());
+    zip.extend_from_slice(&central_directory_offset.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    zip
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
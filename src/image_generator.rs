@@ -1,4 +1,5 @@
 use magick_rust::{
+    CompositeOperator,
     DrawingWand,
     MagickWand,
     PixelWand,
@@ -6,14 +7,339 @@ use magick_rust::{
     GravityType,
 };
 
-pub async fn create_text_image(
+use std::cmp;
+use matrix_sdk::ruma::serde::Base64;
+use qrcode::{Color as QrColor, QrCode};
+
+use crate::file_generator::crc32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    WebP,
+}
+
+impl AnimationFormat {
+    pub fn parse(format: &str) -> Option<AnimationFormat> {
+        match format.to_ascii_lowercase().as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "apng" | "png" => Some(AnimationFormat::Apng),
+            "webp" => Some(AnimationFormat::WebP),
+            _ => None,
+        }
+    }
+
+    pub fn mimetype(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "image/gif",
+            // Most clients and servers only know APNG by its fallback type
+            AnimationFormat::Apng => "image/png",
+            AnimationFormat::WebP => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::WebP => "webp",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    WebP,
+    Avif,
+    Bmp,
+    Tiff,
+    Heic,
+    Svg,
+}
+
+impl ImageFormat {
+    pub fn parse(format: &str) -> Option<ImageFormat> {
+        match format.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpeg" | "jpg" => Some(ImageFormat::Jpeg),
+            "webp" => Some(ImageFormat::WebP),
+            "avif" => Some(ImageFormat::Avif),
+            "bmp" => Some(ImageFormat::Bmp),
+            "tiff" | "tif" => Some(ImageFormat::Tiff),
+            "heic" | "heif" => Some(ImageFormat::Heic),
+            "svg" => Some(ImageFormat::Svg),
+            _ => None,
+        }
+    }
+
+    pub fn mimetype(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::WebP => "image/webp",
+            ImageFormat::Avif => "image/avif",
+            ImageFormat::Bmp => "image/bmp",
+            ImageFormat::Tiff => "image/tiff",
+            ImageFormat::Heic => "image/heic",
+            ImageFormat::Svg => "image/svg+xml",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::WebP => "webp",
+            ImageFormat::Avif => "avif",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Tiff => "tiff",
+            ImageFormat::Heic => "heic",
+            ImageFormat::Svg => "svg",
+        }
+    }
+
+    fn magick_format(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "PNG",
+            ImageFormat::Jpeg => "JPEG",
+            ImageFormat::WebP => "WEBP",
+            ImageFormat::Avif => "AVIF",
+            ImageFormat::Bmp => "BMP",
+            ImageFormat::Tiff => "TIFF",
+            ImageFormat::Heic => "HEIC",
+            ImageFormat::Svg => "SVG",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageEncoding {
+    pub format: ImageFormat,
+    // Compression quality from 1 to 100, for lossy formats
+    pub quality: Option<usize>,
+    // EXIF orientation from 1 to 8, only written for JPEG. The pixels are transformed such that
+    // clients respecting the orientation show the image upright.
+    pub orientation: Option<u16>,
+}
+
+impl Default for ImageEncoding {
+    fn default() -> Self {
+        ImageEncoding {
+            format: ImageFormat::Png,
+            quality: None,
+            orientation: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImagePattern {
+    Gradient,
+    Checkerboard,
+    ColorBars,
+    Alpha,
+    Grid,
+    // Grid with extreme aspect ratios
+    Tall,
+    Wide,
+}
+
+impl ImagePattern {
+    pub fn parse(pattern: &str) -> Option<ImagePattern> {
+        match pattern.to_ascii_lowercase().as_str() {
+            "gradient" => Some(ImagePattern::Gradient),
+            "checkerboard" | "checker" => Some(ImagePattern::Checkerboard),
+            "colorbars" | "bars" => Some(ImagePattern::ColorBars),
+            "alpha" | "transparency" => Some(ImagePattern::Alpha),
+            "grid" => Some(ImagePattern::Grid),
+            "tall" => Some(ImagePattern::Tall),
+            "wide" => Some(ImagePattern::Wide),
+            _ => None,
+        }
+    }
+
+    // Dimensions to use if the user didn't ask for any
+    pub fn default_size(&self, max_size: usize) -> Option<(usize, usize)> {
+        match self {
+            ImagePattern::Tall => Some((cmp::max(max_size / 20, 1), max_size)),
+            ImagePattern::Wide => Some((max_size, cmp::max(max_size / 20, 1))),
+            _ => None,
+        }
+    }
+
+    fn has_grid(&self) -> bool {
+        matches!(self, ImagePattern::Grid | ImagePattern::Tall | ImagePattern::Wide)
+    }
+}
+
+const GRID_LABEL_SPACING: usize = 100;
+
+// RGBA pixels of the pattern, without any text
+pub fn pattern_rgba(pattern: ImagePattern, width: usize, height: usize) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let fx = x as f64 / cmp::max(width - 1, 1) as f64;
+            let fy = y as f64 / cmp::max(height - 1, 1) as f64;
+            let pixel: [u8; 4] = match pattern {
+                ImagePattern::Gradient => {
+                    // Hue from left to right, fading to black towards the bottom
+                    let [r, g, b] = hue_rgb(360.0 * fx);
+                    let brightness = 1.0 - fy;
+                    [(r as f64 * brightness) as u8, (g as f64 * brightness) as u8, (b as f64 * brightness) as u8, 255]
+                }
+                ImagePattern::Checkerboard => {
+                    if (x / 8 + y / 8) % 2 == 0 { [0, 0, 0, 255] } else { [255, 255, 255, 255] }
+                }
+                ImagePattern::ColorBars => {
+                    const BARS: [[u8; 3]; 7] = [
+                        [191, 191, 191],
+                        [191, 191, 0],
+                        [0, 191, 191],
+                        [0, 191, 0],
+                        [191, 0, 191],
+                        [191, 0, 0],
+                        [0, 0, 191],
+                    ];
+                    let [r, g, b] = BARS[cmp::min(x * BARS.len() / width, BARS.len() - 1)];
+                    [r, g, b, 255]
+                }
+                ImagePattern::Alpha => {
+                    // Hue from top to bottom, fully transparent on the left to opaque on the right
+                    let [r, g, b] = hue_rgb(360.0 * fy);
+                    [r, g, b, (255.0 * fx) as u8]
+                }
+                ImagePattern::Grid | ImagePattern::Tall | ImagePattern::Wide => {
+                    if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                        // Border to see whether clients crop anything
+                        [255, 0, 0, 255]
+                    } else if x % GRID_LABEL_SPACING == 0 || y % GRID_LABEL_SPACING == 0 {
+                        [0, 0, 0, 255]
+                    } else if x % 50 == 0 || y % 50 == 0 {
+                        [128, 128, 128, 255]
+                    } else if x % 10 == 0 || y % 10 == 0 {
+                        [208, 208, 208, 255]
+                    } else {
+                        [255, 255, 255, 255]
+                    }
+                }
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    rgba
+}
+
+fn create_pattern_wand(
+    pattern: ImagePattern,
+    text: Option<&String>,
+    width: usize,
+    height: usize,
+    font_size: f64,
+) -> Result<MagickWand, MagickError> {
+    let mut transparent = PixelWand::new();
+    transparent.set_color("none")?;
+
+    let mut magick = MagickWand::new();
+    magick.new_image(width, height, &transparent)?;
+    magick.import_image_pixels(0, 0, width, height, &pattern_rgba(pattern, width, height), "RGBA")?;
+
+    let mut text_color = PixelWand::new();
+    text_color.set_color("#000000")?;
+    let mut text_background = PixelWand::new();
+    text_background.set_color("#ffffffc0")?;
+
+    if pattern.has_grid() {
+        let mut labels = DrawingWand::new();
+        labels.set_fill_color(&text_color);
+        labels.set_text_under_color(&text_background);
+        labels.set_font("DejaVu-Sans")?;
+        labels.set_font_size(10.0);
+        for y in (0..height).step_by(GRID_LABEL_SPACING) {
+            for x in (0..width).step_by(GRID_LABEL_SPACING) {
+                labels.draw_annotation(x as f64 + 2.0, y as f64 + 12.0, &format!("{x},{y}"))?;
+            }
+        }
+        magick.draw_image(&labels)?;
+    }
+
+    if let Some(text) = text {
+        let mut foreground = DrawingWand::new();
+        foreground.set_fill_color(&text_color);
+        foreground.set_text_under_color(&text_background);
+        foreground.set_gravity(GravityType::Center);
+        foreground.set_font("DejaVu-Sans")?;
+        foreground.set_font_size(font_size);
+        foreground.draw_annotation(0.0, 0.0, text)?;
+        magick.draw_image(&foreground)?;
+    }
+
+    Ok(magick)
+}
+
+// A pattern rendered once, with its pixels for placeholder hashes and an optional half size thumbnail
+pub struct PatternImage {
+    pub image: Vec<u8>,
+    pub pixels: Vec<u8>,
+    pub thumbnail: Option<(Vec<u8>, usize, usize)>,
+}
+
+// Blocks while rendering, so run it with spawn_blocking.
+pub fn create_pattern_image(
+    pattern: ImagePattern,
+    text: Option<&String>,
+    width: usize,
+    height: usize,
+    font_size: f64,
+    encoding: &ImageEncoding,
+    with_thumbnail: bool,
+) -> Result<PatternImage, MagickError> {
+    let magick = create_pattern_wand(pattern, text, width, height, font_size)?;
+    let pixels = magick.export_image_pixels(0, 0, width, height, "RGBA")
+        .ok_or_else(|| MagickError("Failed to export image pixels".into()))?;
+    let thumbnail = if with_thumbnail {
+        let thumb_width = cmp::max(width / 2, 1);
+        let thumb_height = cmp::max(height / 2, 1);
+        let thumb = magick.clone();
+        thumb.thumbnail_image(thumb_width, thumb_height)?;
+        Some((encode_pattern(thumb, thumb_width, thumb_height, encoding)?, thumb_width, thumb_height))
+    } else {
+        None
+    };
+    Ok(PatternImage {
+        image: encode_pattern(magick, width, height, encoding)?,
+        pixels,
+        thumbnail,
+    })
+}
+
+fn encode_pattern(magick: MagickWand, width: usize, height: usize, encoding: &ImageEncoding) -> Result<Vec<u8>, MagickError> {
+    if encoding.format == ImageFormat::Svg {
+        // Patterns are raster images, so wrap them into the SVG
+        return embed_in_svg(&magick, width, height);
+    }
+    encode_wand(magick, encoding)
+}
+
+fn embed_in_svg(magick: &MagickWand, width: usize, height: usize) -> Result<Vec<u8>, MagickError> {
+    let png: Base64 = Base64::new(magick.write_image_blob("PNG")?);
+    Ok(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">\
+        <image width=\"{width}\" height=\"{height}\" href=\"data:image/png;base64,{png}\"/></svg>"
+    ).into_bytes())
+}
+
+fn create_text_wand(
     text: Option<&String>,
     background_color: &str,
     foreground_color: &str,
     width: usize,
     height: usize,
     font_size: f64,
-) -> Result<Vec<u8>, MagickError> {
+) -> Result<MagickWand, MagickError> {
     let mut background = PixelWand::new();
     background.set_color(background_color)?;
 
@@ -34,5 +360,467 @@ pub async fn create_text_image(
         magick.draw_image(&foreground)?;
     }
 
-    magick.write_image_blob("PNG")
+    Ok(magick)
+}
+
+pub async fn create_text_image(
+    text: Option<&String>,
+    background_color: &str,
+    foreground_color: &str,
+    width: usize,
+    height: usize,
+    font_size: f64,
+    encoding: &ImageEncoding,
+) -> Result<Vec<u8>, MagickError> {
+    if encoding.format == ImageFormat::Svg {
+        return Ok(create_text_svg(text, background_color, foreground_color, width, height, font_size));
+    }
+
+    let magick = create_text_wand(text, background_color, foreground_color, width, height, font_size)?;
+    encode_wand(magick, encoding)
+}
+
+fn encode_wand(mut magick: MagickWand, encoding: &ImageEncoding) -> Result<Vec<u8>, MagickError> {
+    let orientation = encoding.orientation.filter(|_| encoding.format == ImageFormat::Jpeg);
+    if let Some(orientation) = orientation {
+        // Store the inverse of what the orientation tells viewers to do. For 5-8 the stored pixels
+        // are transposed, but viewers show the image at the requested size again, which is what
+        // callers report in the image info.
+        let background = PixelWand::new();
+        match orientation {
+            2 => magick.flop_image()?,
+            3 => magick.rotate_image(&background, 180.0)?,
+            4 => magick.flip_image()?,
+            5 => {
+                magick.rotate_image(&background, 90.0)?;
+                magick.flop_image()?;
+            }
+            6 => magick.rotate_image(&background, 270.0)?,
+            7 => {
+                magick.rotate_image(&background, 90.0)?;
+                magick.flip_image()?;
+            }
+            8 => magick.rotate_image(&background, 90.0)?,
+            _ => {}
+        }
+    }
+
+    if let Some(quality) = encoding.quality {
+        magick.set_image_compression_quality(quality)?;
+    }
+
+    let image = magick.write_image_blob(encoding.format.magick_format())?;
+
+    Ok(match orientation {
+        Some(orientation) => with_exif_orientation(image, orientation),
+        None => image,
+    })
+}
+
+// Insert an APP1 segment with a minimal EXIF orientation tag after the JPEG start marker, and
+// after the JFIF APP0 segment if there is one since that has to come first
+fn with_exif_orientation(jpeg: Vec<u8>, orientation: u16) -> Vec<u8> {
+    let mut exif = b"Exif\0\0".to_vec();
+    exif.extend_from_slice(b"MM\0\x2a\0\0\0\x08"); // Big endian TIFF header, first IFD at offset 8
+    exif.extend_from_slice(&1u16.to_be_bytes()); // IFD entries
+    exif.extend_from_slice(&0x0112u16.to_be_bytes()); // Orientation tag
+    exif.extend_from_slice(&3u16.to_be_bytes()); // SHORT
+    exif.extend_from_slice(&1u32.to_be_bytes()); // count
+    exif.extend_from_slice(&orientation.to_be_bytes());
+    exif.extend_from_slice(&[0, 0]); // value padding
+    exif.extend_from_slice(&0u32.to_be_bytes()); // no next IFD
+
+    let insert_at = match jpeg.get(2..6) {
+        Some([0xff, 0xe0, high, low]) => 4 + u16::from_be_bytes([*high, *low]) as usize,
+        _ => 2,
+    }.min(jpeg.len());
+
+    let mut result = Vec::with_capacity(jpeg.len() + exif.len() + 4);
+    result.extend_from_slice(&jpeg[..insert_at]);
+    result.extend_from_slice(&[0xff, 0xe1]);
+    result.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+    result.extend_from_slice(&exif);
+    result.extend_from_slice(&jpeg[insert_at..]);
+    result
+}
+
+fn create_text_svg(
+    text: Option<&String>,
+    background_color: &str,
+    foreground_color: &str,
+    width: usize,
+    height: usize,
+    font_size: f64,
+) -> Vec<u8> {
+    let text = text.map(|text| {
+        let text = text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+        format!(
+            "<text x=\"50%\" y=\"50%\" dominant-baseline=\"central\" text-anchor=\"middle\" \
+            font-family=\"DejaVu Sans, sans-serif\" font-size=\"{font_size}\" fill=\"{foreground_color}\">{text}</text>"
+        )
+    }).unwrap_or_default();
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">\
+        <rect width=\"100%\" height=\"100%\" fill=\"{background_color}\"/>{text}</svg>"
+    ).into_bytes()
+}
+
+// Like create_text_image, but returns raw RGB pixels for further encoding. Unlike
+// create_text_image this blocks, so callers generating many frames can use spawn_blocking.
+pub fn create_text_image_rgb(
+    text: Option<&String>,
+    background_color: &str,
+    foreground_color: &str,
+    width: usize,
+    height: usize,
+    font_size: f64,
+) -> anyhow::Result<Vec<u8>> {
+    create_text_image_pixels(text, background_color, foreground_color, width, height, font_size, "RGB")
+}
+
+// Like create_text_image, but returns raw RGBA pixels
+pub fn create_text_image_rgba(
+    text: Option<&String>,
+    background_color: &str,
+    foreground_color: &str,
+    width: usize,
+    height: usize,
+    font_size: f64,
+) -> anyhow::Result<Vec<u8>> {
+    create_text_image_pixels(text, background_color, foreground_color, width, height, font_size, "RGBA")
+}
+
+fn create_text_image_pixels(
+    text: Option<&String>,
+    background_color: &str,
+    foreground_color: &str,
+    width: usize,
+    height: usize,
+    font_size: f64,
+    pixel_map: &str,
+) -> anyhow::Result<Vec<u8>> {
+    create_text_wand(text, background_color, foreground_color, width, height, font_size)?
+        .export_image_pixels(0, 0, width, height, pixel_map)
+        .ok_or(anyhow::anyhow!("Failed to export image pixels"))
+}
+
+pub struct TextRendering<'a> {
+    // Primary font first, followed by fallbacks for glyphs it doesn't have
+    pub fonts: &'a [String],
+    pub font_size: f64,
+    // Text wider than this gets wrapped
+    pub max_width: usize,
+    // Text taller than this is refused
+    pub max_height: usize,
+    pub padding: usize,
+    pub background_color: &'a str,
+    pub foreground_color: &'a str,
+}
+
+// Render arbitrary text with word wrapping, using pango for font fallback, shaping and
+// bidirectional text. The canvas is sized to fit the text. Returns the image with its size.
+// Blocks while rendering, so run it with spawn_blocking.
+pub fn render_text(
+    text: &str,
+    rendering: &TextRendering<'_>,
+    encoding: &ImageEncoding,
+) -> Result<(Vec<u8>, usize, usize), MagickError> {
+    let text = text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let markup = format!(
+        "pango:<span font=\"{}, {}px\" foreground=\"{}\">{text}</span>",
+        rendering.fonts.join(", ").replace('"', ""),
+        rendering.font_size,
+        rendering.foreground_color.replace('"', ""),
+    );
+
+    let mut background = PixelWand::new();
+    background.set_color(rendering.background_color)?;
+
+    let max_text_width = cmp::max(rendering.max_width.saturating_sub(2 * rendering.padding), 1);
+    let max_text_height = cmp::max(rendering.max_height.saturating_sub(2 * rendering.padding), 1);
+    // Pango sizes the canvas to whatever it lays out, so refuse text that could get too large
+    // before rendering anything
+    if estimated_text_height(&text, rendering.font_size, max_text_width) > max_text_height as f64 {
+        return Err(MagickError("Text is too long to render".into()));
+    }
+    let render = |wrap_width: usize| -> Result<MagickWand, MagickError> {
+        let mut text_magick = MagickWand::new();
+        text_magick.set_background_color(&background)?;
+        text_magick.set_option("pango:wrap", "word-char")?;
+        // A width of 0 lets pango lay out everything on as few lines as possible
+        text_magick.set_size(wrap_width, 0)?;
+        text_magick.read_image(&markup)?;
+        Ok(text_magick)
+    };
+    // Without wrapping a long line could get arbitrarily wide, so only try that for short lines
+    let fits_unwrapped = text.lines()
+        .all(|line| estimated_text_width(line, rendering.font_size) <= max_text_width as f64);
+    let mut text_magick = render(if fits_unwrapped { 0 } else { max_text_width })?;
+    if text_magick.get_image_width() > max_text_width {
+        text_magick = render(max_text_width)?;
+    }
+    if text_magick.get_image_height() > max_text_height {
+        return Err(MagickError("Text is too long to render".into()));
+    }
+
+    let width = text_magick.get_image_width() + 2 * rendering.padding;
+    let height = text_magick.get_image_height() + 2 * rendering.padding;
+    let magick = MagickWand::new();
+    magick.new_image(width, height, &background)?;
+    magick.compose_images(
+        &text_magick,
+        CompositeOperator::Over,
+        false,
+        rendering.padding as isize,
+        rendering.padding as isize,
+    )?;
+
+    let image = if encoding.format == ImageFormat::Svg {
+        embed_in_svg(&magick, width, height)?
+    } else {
+        encode_wand(magick, encoding)?
+    };
+    Ok((image, width, height))
+}
+
+// Pessimistic sizes for text laid out by pango, assuming wide glyphs and generous line spacing
+fn estimated_text_width(line: &str, font_size: f64) -> f64 {
+    line.chars().count() as f64 * font_size * 1.5
+}
+
+fn estimated_text_height(text: &str, font_size: f64, wrap_width: usize) -> f64 {
+    let lines: f64 = text.lines()
+        .map(|line| (estimated_text_width(line, font_size) / wrap_width as f64).ceil().max(1.0))
+        .sum();
+    lines * font_size * 1.6
+}
+
+const GRID_PADDING: usize = 8;
+const GRID_LABEL_HEIGHT: usize = 16;
+
+// Labeled images in a grid, each cell sized to fit the largest image. Images that can not be
+// read are left out. Returns the image with its size.
+pub async fn create_image_grid(
+    images: &[(String, Vec<u8>)],
+    columns: usize,
+    encoding: &ImageEncoding,
+) -> Result<(Vec<u8>, usize, usize), MagickError> {
+    let mut tiles = Vec::new();
+    for (label, data) in images {
+        let tile = MagickWand::new();
+        if tile.read_image_blob(data).is_ok() {
+            tiles.push((label, tile));
+        }
+    }
+    if tiles.is_empty() {
+        return Err(MagickError("No readable images for grid".into()));
+    }
+
+    let cell_width = tiles.iter().map(|(_, tile)| tile.get_image_width()).max().unwrap_or(1) + GRID_PADDING;
+    let cell_height = tiles.iter().map(|(_, tile)| tile.get_image_height()).max().unwrap_or(1)
+        + GRID_LABEL_HEIGHT + GRID_PADDING;
+    let columns = cmp::max(cmp::min(columns, tiles.len()), 1);
+    let rows = tiles.len().div_ceil(columns);
+    let width = columns * cell_width + GRID_PADDING;
+    let height = rows * cell_height + GRID_PADDING;
+
+    let mut background = PixelWand::new();
+    background.set_color("#808080")?;
+    let mut text_color = PixelWand::new();
+    text_color.set_color("#ffffff")?;
+
+    let mut magick = MagickWand::new();
+    magick.new_image(width, height, &background)?;
+    let mut labels = DrawingWand::new();
+    labels.set_fill_color(&text_color);
+    labels.set_font("DejaVu-Sans")?;
+    labels.set_font_size(12.0);
+    for (i, (label, tile)) in tiles.iter().enumerate() {
+        let x = GRID_PADDING + (i % columns) * cell_width;
+        let y = GRID_PADDING + (i / columns) * cell_height;
+        labels.draw_annotation(x as f64, (y + GRID_LABEL_HEIGHT - 4) as f64, label)?;
+        magick.compose_images(tile, CompositeOperator::Over, false, x as isize, (y + GRID_LABEL_HEIGHT) as isize)?;
+    }
+    magick.draw_image(&labels)?;
+
+    let image = if encoding.format == ImageFormat::Svg {
+        embed_in_svg(&magick, width, height)?
+    } else {
+        encode_wand(magick, encoding)?
+    };
+    Ok((image, width, height))
+}
+
+// Modules of white border around QR codes, as required by the spec
+const QR_QUIET_ZONE: usize = 4;
+
+// Black on white QR code for the text, with each module scaled to module_size pixels.
+// Returns the image with its width, QR codes are always square.
+pub async fn create_qr_code(
+    text: &str,
+    module_size: usize,
+    encoding: &ImageEncoding,
+) -> anyhow::Result<(Vec<u8>, usize)> {
+    let code = QrCode::new(text.as_bytes())?;
+    let modules = code.width();
+    let colors = code.to_colors();
+    let size = (modules + 2 * QR_QUIET_ZONE) * module_size;
+
+    let mut pixels = vec![255u8; size * size];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != QrColor::Dark {
+            continue;
+        }
+        let x = (i % modules + QR_QUIET_ZONE) * module_size;
+        let y = (i / modules + QR_QUIET_ZONE) * module_size;
+        for row in y..y + module_size {
+            pixels[row * size + x..row * size + x + module_size].fill(0);
+        }
+    }
+
+    let mut white = PixelWand::new();
+    white.set_color("#ffffff")?;
+    let mut magick = MagickWand::new();
+    magick.new_image(size, size, &white)?;
+    magick.import_image_pixels(0, 0, size, size, &pixels, "I")?;
+
+    let image = if encoding.format == ImageFormat::Svg {
+        embed_in_svg(&magick, size, size)?
+    } else {
+        encode_wand(magick, encoding)?
+    };
+    Ok((image, size))
+}
+
+// Fully saturated color for the given hue in degrees, as "#rrggbb"
+pub fn hue_color(hue: f64) -> String {
+    // Not too bright, so white text stays readable
+    let [r, g, b] = hue_rgb(hue).map(|c| (c as f64 * 0.75) as u8);
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn hue_rgb(hue: f64) -> [u8; 3] {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    [r, g, b].map(|c: f64| (c * 255.0) as u8)
+}
+
+// Counter animation with a background color cycling through all hues. Blocks while drawing
+// the frames, so run it with spawn_blocking.
+pub fn create_animated_counter_image(
+    frame_count: usize,
+    format: AnimationFormat,
+    width: usize,
+    height: usize,
+    font_size: f64,
+    delay_ms: u32,
+) -> anyhow::Result<Vec<u8>> {
+    let start_hue: f64 = rand::random_range(0.0..360.0);
+    let frame_color = |i: usize| hue_color(start_hue + 360.0 * i as f64 / frame_count as f64);
+
+    if format == AnimationFormat::Apng {
+        let mut frames = Vec::with_capacity(frame_count);
+        for i in 0..frame_count {
+            frames.push(create_text_image_rgb(Some(&(i+1).to_string()), &frame_color(i), "#ffffff", width, height, font_size)?);
+        }
+        return Ok(create_apng(&frames, width, height, delay_ms));
+    }
+
+    let mut animation = MagickWand::new();
+    // Applied to all images read afterwards, in ticks of 1/100 s
+    animation.set_option("delay", &(delay_ms / 10).to_string())?;
+    for i in 0..frame_count {
+        let frame = create_text_wand(Some(&(i+1).to_string()), &frame_color(i), "#ffffff", width, height, font_size)?;
+        animation.read_image_blob(encode_wand(frame, &ImageEncoding::default())?)?;
+    }
+    let format = match format {
+        AnimationFormat::Gif => "GIF",
+        _ => "WEBP",
+    };
+    Ok(animation.write_images_blob(format)?)
+}
+
+// ImageMagick can only write APNG through ffmpeg, so we write it ourselves with uncompressed deflate blocks
+fn create_apng(frames: &[Vec<u8>], width: usize, height: usize, delay_ms: u32) -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bit RGB, no interlacing
+    png_chunk(&mut png, b"IHDR", &ihdr);
+
+    let mut actl = Vec::new();
+    actl.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    actl.extend_from_slice(&0u32.to_be_bytes()); // loop forever
+    png_chunk(&mut png, b"acTL", &actl);
+
+    let mut sequence_number = 0u32;
+    for (i, frame) in frames.iter().enumerate() {
+        let mut fctl = Vec::new();
+        fctl.extend_from_slice(&sequence_number.to_be_bytes());
+        fctl.extend_from_slice(&(width as u32).to_be_bytes());
+        fctl.extend_from_slice(&(height as u32).to_be_bytes());
+        fctl.extend_from_slice(&[0; 8]); // x and y offset
+        fctl.extend_from_slice(&(cmp::min(delay_ms, u16::MAX as u32) as u16).to_be_bytes());
+        fctl.extend_from_slice(&1000u16.to_be_bytes());
+        fctl.extend_from_slice(&[0, 0]); // dispose_op, blend_op
+        png_chunk(&mut png, b"fcTL", &fctl);
+        sequence_number += 1;
+
+        let data = zlib_stored(&png_scanlines(frame, width));
+        if i == 0 {
+            png_chunk(&mut png, b"IDAT", &data);
+        } else {
+            let mut fdat = sequence_number.to_be_bytes().to_vec();
+            fdat.extend_from_slice(&data);
+            png_chunk(&mut png, b"fdAT", &fdat);
+            sequence_number += 1;
+        }
+    }
+
+    png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+pub fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let crc_start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[crc_start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn png_scanlines(rgb: &[u8], width: usize) -> Vec<u8> {
+    rgb.chunks(width * 3).flat_map(|line| std::iter::once(0).chain(line.iter().copied())).collect()
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut result = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        result.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        result.push(if blocks.peek().is_none() { 1 } else { 0 });
+        result.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        result.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        result.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    result.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    result
 }
@@ -0,0 +1,97 @@
+// Image packs for custom emoji and stickers, see
+// https://github.com/matrix-org/matrix-spec-proposals/pull/2545
+use std::collections::BTreeMap;
+
+use matrix_sdk::ruma::{
+    events::{
+        macros::EventContent, room::ImageInfo, PossiblyRedactedStateEventContent,
+        StateEventType, StaticEventContent,
+    },
+    OwnedMxcUri,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackUsage {
+    Emoticon,
+    Sticker,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PackInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<OwnedMxcUri>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub usage: Vec<PackUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PackImage {
+    pub url: OwnedMxcUri,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<ImageInfo>,
+    // Falls back to the pack usage if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub usage: Vec<PackUsage>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ImagePack {
+    #[serde(default)]
+    pub images: BTreeMap<String, PackImage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pack: Option<PackInfo>,
+}
+
+// Pack shared with everyone in the room, the state key distinguishes multiple packs
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(
+    type = "im.ponies.room_emotes",
+    kind = State,
+    state_key_type = String,
+    custom_possibly_redacted
+)]
+pub struct RoomImagePackContent {
+    #[serde(flatten)]
+    pub pack: ImagePack,
+}
+
+// The macro can't generate this one for a flattened field, a redacted pack is just empty
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PossiblyRedactedRoomImagePackContent {
+    #[serde(flatten)]
+    pub pack: ImagePack,
+}
+
+impl PossiblyRedactedStateEventContent for PossiblyRedactedRoomImagePackContent {
+    type StateKey = String;
+
+    fn event_type(&self) -> StateEventType {
+        RoomImagePackContent::TYPE.into()
+    }
+}
+
+impl StaticEventContent for PossiblyRedactedRoomImagePackContent {
+    const TYPE: &'static str = RoomImagePackContent::TYPE;
+    type IsPrefix = <RoomImagePackContent as StaticEventContent>::IsPrefix;
+}
+
+// Personal pack of the bot
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "im.ponies.user_emotes", kind = GlobalAccountData)]
+pub struct UserImagePackContent {
+    #[serde(flatten)]
+    pub pack: ImagePack,
+}
+
+// Shortcodes are often written with surrounding colons, but stored without
+pub fn normalize_shortcode(shortcode: &str) -> String {
+    shortcode.trim_matches(':').to_string()
+}
@@ -13,20 +13,40 @@ use matrix_sdk::{
             },
             member::StrippedRoomMemberEvent,
         },
+        OwnedRoomId,
+        OwnedUserId,
     },
     RoomMemberships,
 };
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::path::PathBuf;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use tokio::fs;
 use tokio::time::{sleep, Duration};
 
 mod command;
 mod users;
 mod image_generator;
+mod file_generator;
+mod video_generator;
+mod bad_media;
+mod blurhash;
 mod bridge;
-use crate::command::handle_command;
+mod stickers;
+mod image_pack;
+mod audio;
+mod audio_generator;
+mod tts;
+mod transcribe;
+mod media_info;
+mod upload_cache;
+use crate::command::{handle_command, handle_auto_read};
 use crate::users::is_user_trusted;
+use crate::stickers::{Sticker, load_stickers};
+use crate::tts::Tts;
+use crate::transcribe::Transcriber;
+use crate::upload_cache::UploadCache;
 
 // Things we want to pass to message/event handlers
 #[derive(Clone)]
@@ -37,6 +57,13 @@ struct WipContext {
     allowed_pings: Vec<String>,
     launched_ts: u128,
     media_client: Option<Client>,
+    upload_cache: Arc<UploadCache>,
+    // Never empty
+    stickers: Arc<Vec<Sticker>>,
+    tts: Option<Arc<Tts>>,
+    transcriber: Option<Arc<Transcriber>>,
+    // User whose messages get read aloud, by room
+    auto_read: Arc<Mutex<HashMap<OwnedRoomId, OwnedUserId>>>,
 }
 
 #[tokio::main]
@@ -122,6 +149,9 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    let upload_cache = Arc::new(UploadCache::load(&data_dir).await);
+    let stickers = load_stickers(&config, media_client.as_ref().unwrap_or(&bot_client), &upload_cache).await;
+
     let wip_context = WipContext {
         config: config.clone(),
         bot_name: bot_name.unwrap_or("WIP-Bot".to_string()),
@@ -132,6 +162,11 @@ async fn main() -> anyhow::Result<()> {
             .unwrap_or_default()
             .as_millis(),
         media_client,
+        upload_cache,
+        stickers: Arc::new(stickers),
+        tts: Tts::load(&config).map(Arc::new),
+        transcriber: Transcriber::load(&config).map(Arc::new),
+        auto_read: Arc::new(Mutex::new(HashMap::new())),
     };
 
     bot_client.add_event_handler_context(wip_context);
@@ -260,6 +295,11 @@ async fn handle_message(
         cmd
     };
 
+    // Messages read aloud are not commands, even in a DM
+    if !cmd.starts_with('!') && !is_mention && handle_auto_read(&event, &room, &wip_context.0).await {
+        return;
+    }
+
     // Commands start with '!', or is a mention,
     // or was sent in a DM.
     if cmd.starts_with('!') {
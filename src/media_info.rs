@@ -0,0 +1,170 @@
+// What a downloaded attachment actually contains, independent of what the event claims
+use std::time::Duration;
+
+use magick_rust::MagickWand;
+use sha2::{Digest, Sha256};
+
+use crate::audio;
+
+#[derive(Clone, Debug, Default)]
+pub struct MediaInfo {
+    pub size: usize,
+    pub mimetype: Option<&'static str>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub duration: Option<Duration>,
+    pub sha256: String,
+}
+
+// Blocking for larger media, so better call it from a blocking task
+pub fn probe(data: &[u8]) -> MediaInfo {
+    let mimetype = sniff_mimetype(data);
+    let mut info = MediaInfo {
+        size: data.len(),
+        mimetype,
+        sha256: Sha256::digest(data).iter().map(|b| format!("{b:02x}")).collect(),
+        ..MediaInfo::default()
+    };
+    match mimetype {
+        Some("video/mp4") | Some("video/quicktime") => {
+            if let Some((dimensions, duration)) = mp4_info(data) {
+                info.width = dimensions.map(|d| d.0);
+                info.height = dimensions.map(|d| d.1);
+                info.duration = duration;
+            }
+        }
+        Some(mimetype) if mimetype.starts_with("audio/") => {
+            info.duration = audio::decode(data).ok().map(|audio| audio.duration());
+        }
+        Some(mimetype) if mimetype.starts_with("image/") => {
+            let magick = MagickWand::new();
+            if magick.read_image_blob(data).is_ok() {
+                info.width = Some(magick.get_image_width());
+                info.height = Some(magick.get_image_height());
+            }
+        }
+        _ => {}
+    }
+    info
+}
+
+// Guess the mimetype from magic bytes, like browsers do
+pub fn sniff_mimetype(data: &[u8]) -> Option<&'static str> {
+    let starts = |magic: &[u8]| data.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+    if starts(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if starts(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        Some("image/gif")
+    } else if starts(b"RIFF") && at(8, b"WEBP") {
+        Some("image/webp")
+    } else if starts(b"RIFF") && at(8, b"WAVE") {
+        Some("audio/wav")
+    } else if starts(b"BM") {
+        Some("image/bmp")
+    } else if starts(b"II*\0") || starts(b"MM\0*") {
+        Some("image/tiff")
+    } else if at(4, b"ftyp") {
+        match data.get(8..12)? {
+            b"avif" | b"avis" => Some("image/avif"),
+            b"heic" | b"heix" | b"mif1" | b"msf1" => Some("image/heic"),
+            b"qt  " => Some("video/quicktime"),
+            b"M4A " => Some("audio/mp4"),
+            _ => Some("video/mp4"),
+        }
+    } else if starts(b"\x1a\x45\xdf\xa3") {
+        Some("video/webm")
+    } else if starts(b"OggS") {
+        Some("audio/ogg")
+    } else if starts(b"ID3") || (data.len() > 1 && data[0] == 0xff && data[1] & 0xe0 == 0xe0) {
+        Some("audio/mpeg")
+    } else if starts(b"fLaC") {
+        Some("audio/flac")
+    } else if starts(b"%PDF-") {
+        Some("application/pdf")
+    } else if starts(b"PK\x03\x04") {
+        Some("application/zip")
+    } else if is_svg(data) {
+        Some("image/svg+xml")
+    } else if std::str::from_utf8(data).is_ok() {
+        Some("text/plain")
+    } else {
+        None
+    }
+}
+
+fn is_svg(data: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&data[..data.len().min(1024)]).to_ascii_lowercase();
+    let head = head.trim_start();
+    (head.starts_with("<?xml") || head.starts_with("<svg")) && head.contains("<svg")
+}
+
+// Iterate over the boxes in an ISO BMFF container, as (type, body)
+fn mp4_boxes(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut position = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(position..position + 8)?;
+        let mut size = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+        let mut header_len = 8;
+        if size == 1 {
+            size = usize::try_from(u64::from_be_bytes(data.get(position + 8..position + 16)?.try_into().ok()?)).ok()?;
+            header_len = 16;
+        } else if size == 0 {
+            size = data.len() - position;
+        }
+        // Sizes come from the file, so don't trust them to fit
+        if size < header_len || size > data.len() - position {
+            return None;
+        }
+        let end = position.checked_add(size)?;
+        let body = data.get(position + header_len..end)?;
+        let box_type = &header[4..8];
+        position = end;
+        Some((box_type, body))
+    })
+}
+
+type Dimensions = (usize, usize);
+
+// Dimensions of the first video track and movie duration
+fn mp4_info(data: &[u8]) -> Option<(Option<Dimensions>, Option<Duration>)> {
+    let (_, moov) = mp4_boxes(data).find(|(box_type, _)| *box_type == b"moov")?;
+    let mut dimensions = None;
+    let mut duration = None;
+    for (box_type, body) in mp4_boxes(moov) {
+        match box_type {
+            b"mvhd" => {
+                let read_u32 = |offset: usize| Some(u32::from_be_bytes(body.get(offset..offset + 4)?.try_into().ok()?) as u64);
+                let read_u64 = |offset: usize| Some(u64::from_be_bytes(body.get(offset..offset + 8)?.try_into().ok()?));
+                // Version 1 uses 64 bit creation/modification times and duration
+                let (timescale, length) = if *body.first()? == 1 {
+                    (read_u32(20)?, read_u64(24)?)
+                } else {
+                    (read_u32(12)?, read_u32(16)?)
+                };
+                if timescale > 0 {
+                    duration = Some(Duration::from_secs_f64(length as f64 / timescale as f64));
+                }
+            }
+            b"trak" if dimensions.is_none() => {
+                let Some((_, tkhd)) = mp4_boxes(body).find(|(box_type, _)| *box_type == b"tkhd") else {
+                    continue;
+                };
+                // Width and height are 16.16 fixed point at the very end
+                let Some(end) = tkhd.len().checked_sub(8) else {
+                    continue;
+                };
+                let width = u32::from_be_bytes(tkhd[end..end + 4].try_into().ok()?) >> 16;
+                let height = u32::from_be_bytes(tkhd[end + 4..end + 8].try_into().ok()?) >> 16;
+                // Audio tracks have no dimensions
+                if width > 0 && height > 0 {
+                    dimensions = Some((width as usize, height as usize));
+                }
+            }
+            _ => {}
+        }
+    }
+    Some((dimensions, duration))
+}
@@ -0,0 +1,172 @@
+use config::Config;
+use log::{debug, info, warn};
+use magick_rust::MagickWand;
+use matrix_sdk::{
+    Client,
+    ruma::{
+        assign,
+        events::room::ImageInfo,
+        OwnedMxcUri,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::image_generator::{self, ImageEncoding};
+use crate::upload_cache::UploadCache;
+
+// Used if no stickers are configured and uploading a generated one failed, these only load if
+// their servers are reachable
+const DEFAULT_STICKERS: &[&str] = &[
+    "mxc://spiritcroc.de/mkJFKqrNzBGBcILPTIPlTPOV",
+    "mxc://local.beeper.com/imjoshin_slS22Mgs4Vnp8cj8D0sPcU6Yniu01mqagZRkdG9Ue6a48B1sOzO7qFxk3ZhpwrJ3",
+    "mxc://local.beeper.com/imjoshin_dFbm02esvz1eosgPF7ZTzWk4DunfU9xTcIsLr49qNiXHRypTiPFmq4lKBYmjKCiZ",
+    "mxc://local.beeper.com/spiritcroc_TnTnE9q1fOXD0gtA9UlcBrStvCVLWwAbAtKcAIOKqWJRtQeZwsniGSRVA8xV51tB",
+];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Sticker {
+    pub body: String,
+    pub mxc: OwnedMxcUri,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub mimetype: Option<String>,
+}
+
+impl Sticker {
+    pub fn image_info(&self) -> ImageInfo {
+        assign!(ImageInfo::new(), {
+            width: self.width.map(Into::into),
+            height: self.height.map(Into::into),
+            size: self.size.and_then(|s| s.try_into().ok()),
+            mimetype: self.mimetype.clone(),
+        })
+    }
+}
+
+fn mimetype_for(path: &Path) -> Option<&'static str> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "png" | "apng" => Some("image/png"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "avif" => Some("image/avif"),
+        _ => None,
+    }
+}
+
+// Stickers from the config, followed by all images in the configured sticker directory.
+// Falls back to a generated sticker if neither is configured.
+pub async fn load_stickers(config: &Config, client: &Client, upload_cache: &UploadCache) -> Vec<Sticker> {
+    let mut stickers = config.get::<Vec<Sticker>>("stickers.mxc").unwrap_or_default();
+
+    if let Ok(directory) = config.get::<String>("stickers.directory") {
+        match upload_sticker_directory(client, upload_cache, &PathBuf::from(directory)).await {
+            Ok(uploaded) => stickers.extend(uploaded),
+            Err(e) => warn!("Failed to load sticker directory: {e}"),
+        }
+    }
+
+    if stickers.is_empty() {
+        warn!("No stickers configured, uploading a generated one");
+        match upload_default_sticker(client, upload_cache).await {
+            Ok(sticker) => stickers.push(sticker),
+            Err(e) => warn!("Failed to upload the generated sticker, using built-in remote ones: {e}"),
+        }
+    }
+    if stickers.is_empty() {
+        stickers = DEFAULT_STICKERS.iter().enumerate().map(|(i, mxc)| Sticker {
+            body: format!("Sticker {}", i + 1),
+            mxc: (*mxc).into(),
+            width: None,
+            height: None,
+            size: None,
+            mimetype: None,
+        }).collect();
+    }
+    info!("Loaded {} stickers", stickers.len());
+    stickers
+}
+
+// Files that were uploaded before are found in the upload cache, so only new content gets uploaded
+async fn upload_sticker_directory(
+    client: &Client,
+    upload_cache: &UploadCache,
+    directory: &Path,
+) -> anyhow::Result<Vec<Sticker>> {
+    let mut paths = Vec::new();
+    let mut entries = fs::read_dir(directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        paths.push(entry.path());
+    }
+    paths.sort();
+
+    let mut stickers = Vec::new();
+    for path in paths {
+        let Some(mimetype) = mimetype_for(&path) else {
+            continue;
+        };
+        match upload_sticker(client, upload_cache, &path, mimetype).await {
+            Ok(sticker) => stickers.push(sticker),
+            Err(e) => warn!("Failed to upload sticker {}: {e}", path.display()),
+        }
+    }
+    Ok(stickers)
+}
+
+async fn upload_sticker(client: &Client, upload_cache: &UploadCache, path: &Path, mimetype: &str) -> anyhow::Result<Sticker> {
+    let data = fs::read(path).await?;
+    let (width, height) = {
+        let magick = MagickWand::new();
+        magick.read_image_blob(&data)?;
+        (magick.get_image_width(), magick.get_image_height())
+    };
+    let size = data.len();
+
+    let mime: mime::Mime = mimetype.parse()?;
+    // Renamed or touched files keep their mxc
+    let mxc = upload_cache.upload(client, &mime, data, false).await?;
+    debug!("Sticker {} is at {}", path.display(), mxc);
+
+    Ok(Sticker {
+        body: path.file_stem().and_then(|s| s.to_str()).unwrap_or("Sticker").to_string(),
+        mxc,
+        width: width.try_into().ok(),
+        height: height.try_into().ok(),
+        size: size.try_into().ok(),
+        mimetype: Some(mimetype.to_string()),
+    })
+}
+
+async fn upload_default_sticker(client: &Client, upload_cache: &UploadCache) -> anyhow::Result<Sticker> {
+    const SIZE: usize = 256;
+    let encoding = ImageEncoding::default();
+    let data = image_generator::create_text_image(
+        Some(&"WIP".to_string()),
+        "#3f51b5",
+        "#ffffff",
+        SIZE,
+        SIZE,
+        96.0,
+        &encoding,
+    ).await?;
+    let size = data.len();
+    let mimetype = encoding.format.mimetype();
+    // Same image every time, so this is only uploaded once per homeserver
+    let mxc = upload_cache.upload(client, &mimetype.parse()?, data, false).await?;
+
+    Ok(Sticker {
+        body: "WIP".to_string(),
+        mxc,
+        width: SIZE.try_into().ok(),
+        height: SIZE.try_into().ok(),
+        size: size.try_into().ok(),
+        mimetype: Some(mimetype.to_string()),
+    })
+}
@@ -0,0 +1,55 @@
+use anyhow::anyhow;
+use config::Config;
+use log::{info, warn};
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+
+use crate::audio::Audio;
+
+// Whisper only takes 16kHz mono
+const WHISPER_SAMPLE_RATE: u32 = 16_000;
+const AUTO_LANGUAGE: &str = "auto";
+
+// Whisper models are big, so we load the configured one once at startup
+pub struct Transcriber {
+    context: WhisperContext,
+    language: String,
+}
+
+impl Transcriber {
+    // Model from `transcribe.model_path`, language from `transcribe.language`
+    pub fn load(config: &Config) -> Option<Transcriber> {
+        let model_path = config.get::<String>("transcribe.model_path").ok()?;
+        let language = config.get::<String>("transcribe.language").unwrap_or(AUTO_LANGUAGE.to_string());
+        match WhisperContext::new_with_params(&model_path, WhisperContextParameters::default()) {
+            Ok(context) => {
+                info!("Loaded transcription model from {model_path}");
+                Some(Transcriber { context, language })
+            }
+            Err(e) => {
+                warn!("Failed to load transcription model from {model_path}: {e}");
+                None
+            }
+        }
+    }
+
+    // Blocking, so better call it from a blocking task
+    pub fn transcribe(&self, audio: &Audio) -> anyhow::Result<String> {
+        let samples = audio.to_mono().resample(WHISPER_SAMPLE_RATE).samples;
+
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_language(Some(&self.language));
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_special(false);
+        params.set_print_timestamps(false);
+
+        let mut state = self.context.create_state().map_err(|e| anyhow!("Failed to create state: {e}"))?;
+        state.full(params, &samples).map_err(|e| anyhow!("Failed to transcribe: {e}"))?;
+
+        let mut segments = Vec::new();
+        for i in 0..state.full_n_segments()? {
+            segments.push(state.full_get_segment_text(i)?.trim().to_string());
+        }
+        Ok(segments.join(" ").trim().to_string())
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use config::Config;
+use log::{info, warn};
+use piper_rs::synth::{AudioOutputConfig, PiperSpeechSynthesizer};
+
+use crate::audio::Audio;
+
+const DEFAULT_VOICE: &str = "default";
+
+struct TtsVoice {
+    synth: PiperSpeechSynthesizer,
+    sample_rate: u32,
+}
+
+// Piper models are slow to load, so we load all configured voices once at startup
+pub struct Tts {
+    // Models keep the selected speaker as state, so only one synthesis per voice at a time
+    voices: HashMap<String, Mutex<TtsVoice>>,
+    default_voice: String,
+}
+
+impl Tts {
+    // Voices from `tts.voices.<name>`, plus `tts.config_path` as the default voice
+    pub fn load(config: &Config) -> Option<Tts> {
+        let mut config_paths = config.get::<HashMap<String, String>>("tts.voices").unwrap_or_default();
+        if let Ok(config_path) = config.get::<String>("tts.config_path") {
+            config_paths.insert(DEFAULT_VOICE.to_string(), config_path);
+        }
+
+        let mut voices = HashMap::new();
+        for (name, config_path) in config_paths {
+            match load_voice(Path::new(&config_path)) {
+                Ok(voice) => {
+                    info!("Loaded TTS voice {name} from {config_path}");
+                    voices.insert(name, Mutex::new(voice));
+                }
+                Err(e) => warn!("Failed to load TTS voice {name} from {config_path}: {e}"),
+            }
+        }
+        if voices.is_empty() {
+            return None;
+        }
+
+        let default_voice = config.get::<String>("tts.default_voice")
+            .ok()
+            .filter(|voice| voices.contains_key(voice))
+            .or_else(|| voices.contains_key(DEFAULT_VOICE).then(|| DEFAULT_VOICE.to_string()))
+            .unwrap_or_else(|| {
+                let mut names: Vec<&String> = voices.keys().collect();
+                names.sort();
+                names[0].clone()
+            });
+        Some(Tts { voices, default_voice })
+    }
+
+    pub fn voice_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.voices.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    // Blocking, so better call it from a blocking task
+    pub fn synthesize(&self, text: &str, voice: Option<&str>, speaker: Option<i64>, speed: Option<f32>) -> anyhow::Result<Audio> {
+        let name = voice.unwrap_or(&self.default_voice);
+        let voice = self.voices.get(name)
+            .ok_or_else(|| anyhow!("Unknown voice {name}, available: {}", self.voice_names().join(", ")))?
+            .lock()
+            .map_err(|_| anyhow!("TTS voice {name} poisoned"))?;
+
+        let model = voice.synth.clone_model();
+        if let Some(e) = model.set_speaker(speaker.unwrap_or(0)) {
+            if speaker.is_some() {
+                return Err(anyhow!("Failed to select speaker: {e}"));
+            }
+        }
+
+        // Piper maps a rate percentage to a speed factor of up to 5
+        let output_config = speed.map(|speed| AudioOutputConfig {
+            rate: Some((speed * 20.0).round().clamp(1.0, 100.0) as u8),
+            volume: None,
+            pitch: None,
+            appended_silence_ms: None,
+        });
+
+        let mut samples = Vec::new();
+        let stream = voice.synth.synthesize_parallel(text.to_string(), output_config)
+            .map_err(|e| anyhow!("Failed to synthesize: {e}"))?;
+        for chunk in stream {
+            samples.append(&mut chunk.map_err(|e| anyhow!("Failed to synthesize: {e}"))?.into_vec());
+        }
+        Ok(Audio::mono(samples, voice.sample_rate))
+    }
+}
+
+fn load_voice(config_path: &Path) -> anyhow::Result<TtsVoice> {
+    let model = piper_rs::from_config_path(config_path).map_err(|e| anyhow!("{e}"))?;
+    let sample_rate = model.audio_output_info().map_err(|e| anyhow!("{e}"))?.sample_rate as u32;
+    let synth = PiperSpeechSynthesizer::new(model).map_err(|e| anyhow!("{e}"))?;
+    Ok(TtsVoice { synth, sample_rate })
+}
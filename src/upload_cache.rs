@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use matrix_sdk::{Client, ruma::OwnedMxcUri};
+use sha2::{Digest, Sha256};
+use tokio::{fs, sync::Mutex};
+
+const UPLOAD_CACHE_FILE: &str = "upload_cache.json";
+
+// Content hash to mxc of everything we uploaded, so identical media is only uploaded once.
+// Entries are never checked against the server, if an upload got lost `--fresh` replaces it.
+pub struct UploadCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, OwnedMxcUri>>,
+}
+
+impl UploadCache {
+    pub async fn load(data_dir: &Path) -> UploadCache {
+        let path = data_dir.join(UPLOAD_CACHE_FILE);
+        let entries: HashMap<String, OwnedMxcUri> = match fs::read_to_string(&path).await {
+            Ok(serialized) => serde_json::from_str(&serialized).unwrap_or_default(),
+            Err(_) => HashMap::new(),
+        };
+        debug!("Loaded {} cached uploads", entries.len());
+        UploadCache { path, entries: Mutex::new(entries) }
+    }
+
+    // Upload unless the same content was uploaded to the same homeserver before.
+    // With `fresh`, always upload and remember the new mxc instead.
+    pub async fn upload(
+        &self,
+        client: &Client,
+        mimetype: &mime::Mime,
+        data: Vec<u8>,
+        fresh: bool,
+    ) -> anyhow::Result<OwnedMxcUri> {
+        let hash: String = Sha256::digest(&data).iter().map(|b| format!("{b:02x}")).collect();
+        // Servers may serve the same content differently depending on the uploaded mimetype
+        let key = format!("{} {} {hash}", client.homeserver(), mimetype.essence_str());
+
+        if !fresh {
+            let cached = self.entries.lock().await.get(&key).cloned();
+            if let Some(mxc) = cached {
+                debug!("Reusing upload {mxc} for {hash}");
+                return Ok(mxc);
+            }
+        }
+
+        let mxc = client.media().upload(mimetype, data, None).await?.content_uri;
+        let mut entries = self.entries.lock().await;
+        entries.insert(key, mxc.clone());
+        self.persist(&entries).await;
+        Ok(mxc)
+    }
+
+    // Stop reusing an upload, e.g. after the server refused a message with it
+    pub async fn forget(&self, mxc: &OwnedMxcUri) {
+        let mut entries = self.entries.lock().await;
+        let count = entries.len();
+        entries.retain(|_, cached| cached != mxc);
+        if entries.len() != count {
+            debug!("Forgot cached upload {mxc}");
+            self.persist(&entries).await;
+        }
+    }
+
+    // Called with the entries locked, so concurrent writes can't leave an older state on disk
+    async fn persist(&self, entries: &HashMap<String, OwnedMxcUri>) {
+        let result = match serde_json::to_string(entries) {
+            Ok(serialized) => fs::write(&self.path, serialized).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            warn!("Failed to persist upload cache: {e}");
+        }
+    }
+}
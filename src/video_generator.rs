@@ -0,0 +1,360 @@
+// Minimal H.264 + MP4 writer, so we can produce playable videos without an encoder library.
+// Every macroblock is stored as uncompressed I_PCM, which is large but valid baseline H.264.
+
+const PROFILE_IDC: u8 = 66; // Baseline
+const CONSTRAINT_FLAGS: u8 = 0xc0; // Constrained baseline
+const LEVEL_IDC: u8 = 51;
+const MB_TYPE_I_PCM: u32 = 25;
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    bit_count: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { bytes: Vec::new(), current: 0, bit_count: 0 }
+    }
+
+    fn bit(&mut self, bit: bool) {
+        self.current = (self.current << 1) | bit as u8;
+        self.bit_count += 1;
+        if self.bit_count == 8 {
+            self.bytes.push(self.current);
+            self.current = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            self.bit((value >> i) & 1 == 1);
+        }
+    }
+
+    // Exp-Golomb unsigned
+    fn ue(&mut self, value: u32) {
+        let value = value + 1;
+        let len = 32 - value.leading_zeros();
+        self.bits(0, len - 1);
+        self.bits(value, len);
+    }
+
+    // Exp-Golomb signed
+    fn se(&mut self, value: i32) {
+        let mapped = if value > 0 { 2 * value - 1 } else { -2 * value };
+        self.ue(mapped as u32);
+    }
+
+    fn align_zero(&mut self) {
+        while self.bit_count != 0 {
+            self.bit(false);
+        }
+    }
+
+    fn aligned_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn finish_rbsp(mut self) -> Vec<u8> {
+        self.bit(true);
+        self.align_zero();
+        self.bytes
+    }
+}
+
+fn nal_unit(nal_ref_idc: u8, nal_unit_type: u8, rbsp: &[u8]) -> Vec<u8> {
+    let mut nal = vec![(nal_ref_idc << 5) | nal_unit_type];
+    // Emulation prevention, so the payload never contains a start code
+    let mut zeros = 0;
+    for &b in rbsp {
+        if zeros >= 2 && b <= 3 {
+            nal.push(3);
+            zeros = 0;
+        }
+        nal.push(b);
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+    }
+    nal
+}
+
+fn sps(width: usize, height: usize) -> Vec<u8> {
+    let mbs_width = width.div_ceil(16);
+    let mbs_height = height.div_ceil(16);
+    let mut w = BitWriter::new();
+    w.bits(PROFILE_IDC as u32, 8);
+    w.bits(CONSTRAINT_FLAGS as u32, 8);
+    w.bits(LEVEL_IDC as u32, 8);
+    w.ue(0); // seq_parameter_set_id
+    w.ue(0); // log2_max_frame_num_minus4
+    w.ue(2); // pic_order_cnt_type
+    w.ue(1); // max_num_ref_frames
+    w.bit(false); // gaps_in_frame_num_value_allowed_flag
+    w.ue(mbs_width as u32 - 1);
+    w.ue(mbs_height as u32 - 1);
+    w.bit(true); // frame_mbs_only_flag
+    w.bit(true); // direct_8x8_inference_flag
+    let crop_right = (mbs_width * 16 - width) / 2;
+    let crop_bottom = (mbs_height * 16 - height) / 2;
+    if crop_right > 0 || crop_bottom > 0 {
+        w.bit(true);
+        w.ue(0);
+        w.ue(crop_right as u32);
+        w.ue(0);
+        w.ue(crop_bottom as u32);
+    } else {
+        w.bit(false);
+    }
+    w.bit(false); // vui_parameters_present_flag
+    nal_unit(3, 7, &w.finish_rbsp())
+}
+
+fn pps() -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.ue(0); // pic_parameter_set_id
+    w.ue(0); // seq_parameter_set_id
+    w.bit(false); // entropy_coding_mode_flag: CAVLC
+    w.bit(false); // bottom_field_pic_order_in_frame_present_flag
+    w.ue(0); // num_slice_groups_minus1
+    w.ue(0); // num_ref_idx_l0_default_active_minus1
+    w.ue(0); // num_ref_idx_l1_default_active_minus1
+    w.bit(false); // weighted_pred_flag
+    w.bits(0, 2); // weighted_bipred_idc
+    w.se(0); // pic_init_qp_minus26
+    w.se(0); // pic_init_qs_minus26
+    w.se(0); // chroma_qp_index_offset
+    w.bit(false); // deblocking_filter_control_present_flag
+    w.bit(false); // constrained_intra_pred_flag
+    w.bit(false); // redundant_pic_cnt_present_flag
+    nal_unit(3, 8, &w.finish_rbsp())
+}
+
+// Convert an RGB frame to padded 4:2:0 planes
+fn yuv_planes(rgb: &[u8], width: usize, height: usize) -> (Vec<u8>, Vec<u8>, Vec<u8>, usize, usize) {
+    let padded_width = width.div_ceil(16) * 16;
+    let padded_height = height.div_ceil(16) * 16;
+    let pixel = |x: usize, y: usize| {
+        let i = (y.min(height - 1) * width + x.min(width - 1)) * 3;
+        (rgb[i] as f64, rgb[i+1] as f64, rgb[i+2] as f64)
+    };
+    let mut y_plane = Vec::with_capacity(padded_width * padded_height);
+    for y in 0..padded_height {
+        for x in 0..padded_width {
+            let (r, g, b) = pixel(x, y);
+            y_plane.push((16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0).round() as u8);
+        }
+    }
+    let mut u_plane = Vec::with_capacity(padded_width * padded_height / 4);
+    let mut v_plane = Vec::with_capacity(padded_width * padded_height / 4);
+    for y in (0..padded_height).step_by(2) {
+        for x in (0..padded_width).step_by(2) {
+            let (mut r, mut g, mut b) = (0.0, 0.0, 0.0);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let p = pixel(x + dx, y + dy);
+                r += p.0 / 4.0;
+                g += p.1 / 4.0;
+                b += p.2 / 4.0;
+            }
+            u_plane.push((128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0).round() as u8);
+            v_plane.push((128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0).round() as u8);
+        }
+    }
+    (y_plane, u_plane, v_plane, padded_width, padded_height)
+}
+
+fn idr_slice(rgb: &[u8], width: usize, height: usize, idr_pic_id: u32) -> Vec<u8> {
+    let (y_plane, u_plane, v_plane, padded_width, padded_height) = yuv_planes(rgb, width, height);
+    let chroma_width = padded_width / 2;
+    let mut w = BitWriter::new();
+    w.ue(0); // first_mb_in_slice
+    w.ue(7); // slice_type: I, for all slices of the picture
+    w.ue(0); // pic_parameter_set_id
+    w.bits(0, 4); // frame_num
+    w.ue(idr_pic_id);
+    w.bit(false); // no_output_of_prior_pics_flag
+    w.bit(false); // long_term_reference_flag
+    w.se(0); // slice_qp_delta
+    for mb_y in 0..padded_height / 16 {
+        for mb_x in 0..padded_width / 16 {
+            w.ue(MB_TYPE_I_PCM);
+            w.align_zero();
+            for y in 0..16 {
+                let start = (mb_y * 16 + y) * padded_width + mb_x * 16;
+                w.aligned_bytes(&y_plane[start..start+16]);
+            }
+            for plane in [&u_plane, &v_plane] {
+                for y in 0..8 {
+                    let start = (mb_y * 8 + y) * chroma_width + mb_x * 8;
+                    w.aligned_bytes(&plane[start..start+8]);
+                }
+            }
+        }
+    }
+    nal_unit(3, 5, &w.finish_rbsp())
+}
+
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(payload.len() + 8);
+    result.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    result.extend_from_slice(kind);
+    result.extend_from_slice(payload);
+    result
+}
+
+fn mp4_full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut full_payload = Vec::with_capacity(payload.len() + 4);
+    full_payload.extend_from_slice(&((version as u32) << 24 | flags).to_be_bytes());
+    full_payload.extend_from_slice(payload);
+    mp4_box(kind, &full_payload)
+}
+
+const MP4_MATRIX: [u32; 9] = [0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000];
+
+fn moov(
+    width: usize,
+    height: usize,
+    fps: u32,
+    sps: &[u8],
+    pps: &[u8],
+    sample_sizes: &[u32],
+    chunk_offset: u32,
+) -> Vec<u8> {
+    let sample_count = sample_sizes.len() as u32;
+    let duration_ms = sample_count * 1000 / fps;
+    let matrix: Vec<u8> = MP4_MATRIX.iter().flat_map(|v| v.to_be_bytes()).collect();
+
+    let mut mvhd = Vec::new();
+    mvhd.extend_from_slice(&[0; 8]); // creation & modification time
+    mvhd.extend_from_slice(&1000u32.to_be_bytes()); // timescale
+    mvhd.extend_from_slice(&duration_ms.to_be_bytes());
+    mvhd.extend_from_slice(&0x00010000u32.to_be_bytes()); // rate
+    mvhd.extend_from_slice(&0x0100u16.to_be_bytes()); // volume
+    mvhd.extend_from_slice(&[0; 10]); // reserved
+    mvhd.extend_from_slice(&matrix);
+    mvhd.extend_from_slice(&[0; 24]); // pre_defined
+    mvhd.extend_from_slice(&2u32.to_be_bytes()); // next_track_ID
+
+    let mut tkhd = Vec::new();
+    tkhd.extend_from_slice(&[0; 8]); // creation & modification time
+    tkhd.extend_from_slice(&1u32.to_be_bytes()); // track_ID
+    tkhd.extend_from_slice(&[0; 4]); // reserved
+    tkhd.extend_from_slice(&duration_ms.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 8]); // reserved
+    tkhd.extend_from_slice(&[0; 8]); // layer, alternate_group, volume, reserved
+    tkhd.extend_from_slice(&matrix);
+    tkhd.extend_from_slice(&((width as u32) << 16).to_be_bytes());
+    tkhd.extend_from_slice(&((height as u32) << 16).to_be_bytes());
+
+    let mut mdhd = Vec::new();
+    mdhd.extend_from_slice(&[0; 8]); // creation & modification time
+    mdhd.extend_from_slice(&fps.to_be_bytes()); // timescale: one tick per frame
+    mdhd.extend_from_slice(&sample_count.to_be_bytes());
+    mdhd.extend_from_slice(&0x55c4u16.to_be_bytes()); // language: und
+    mdhd.extend_from_slice(&[0; 2]);
+
+    let mut hdlr = Vec::new();
+    hdlr.extend_from_slice(&[0; 4]);
+    hdlr.extend_from_slice(b"vide");
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(b"VideoHandler\0");
+
+    let mut avcc = vec![1, PROFILE_IDC, CONSTRAINT_FLAGS, LEVEL_IDC, 0xff, 0xe1];
+    avcc.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    avcc.extend_from_slice(sps);
+    avcc.push(1);
+    avcc.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    avcc.extend_from_slice(pps);
+
+    let mut avc1 = Vec::new();
+    avc1.extend_from_slice(&[0; 6]); // reserved
+    avc1.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+    avc1.extend_from_slice(&[0; 16]); // pre_defined & reserved
+    avc1.extend_from_slice(&(width as u16).to_be_bytes());
+    avc1.extend_from_slice(&(height as u16).to_be_bytes());
+    avc1.extend_from_slice(&0x00480000u32.to_be_bytes()); // 72 dpi
+    avc1.extend_from_slice(&0x00480000u32.to_be_bytes());
+    avc1.extend_from_slice(&[0; 4]); // reserved
+    avc1.extend_from_slice(&1u16.to_be_bytes()); // frame_count
+    avc1.extend_from_slice(&[0; 32]); // compressorname
+    avc1.extend_from_slice(&0x0018u16.to_be_bytes()); // depth
+    avc1.extend_from_slice(&0xffffu16.to_be_bytes()); // pre_defined
+    avc1.extend_from_slice(&mp4_box(b"avcC", &avcc));
+
+    let mut stsd = 1u32.to_be_bytes().to_vec();
+    stsd.extend_from_slice(&mp4_box(b"avc1", &avc1));
+
+    let mut stts = 1u32.to_be_bytes().to_vec();
+    stts.extend_from_slice(&sample_count.to_be_bytes());
+    stts.extend_from_slice(&1u32.to_be_bytes());
+
+    let mut stsc = 1u32.to_be_bytes().to_vec();
+    stsc.extend_from_slice(&1u32.to_be_bytes()); // first_chunk
+    stsc.extend_from_slice(&sample_count.to_be_bytes()); // samples_per_chunk
+    stsc.extend_from_slice(&1u32.to_be_bytes()); // sample_description_index
+
+    let mut stsz = 0u32.to_be_bytes().to_vec();
+    stsz.extend_from_slice(&sample_count.to_be_bytes());
+    for size in sample_sizes {
+        stsz.extend_from_slice(&size.to_be_bytes());
+    }
+
+    let mut stco = 1u32.to_be_bytes().to_vec();
+    stco.extend_from_slice(&chunk_offset.to_be_bytes());
+
+    let stbl = [
+        mp4_full_box(b"stsd", 0, 0, &stsd),
+        mp4_full_box(b"stts", 0, 0, &stts),
+        mp4_full_box(b"stsc", 0, 0, &stsc),
+        mp4_full_box(b"stsz", 0, 0, &stsz),
+        mp4_full_box(b"stco", 0, 0, &stco),
+    ].concat();
+    let mut dref = 1u32.to_be_bytes().to_vec();
+    dref.extend_from_slice(&mp4_full_box(b"url ", 0, 1, &[]));
+    let minf = [
+        mp4_full_box(b"vmhd", 0, 1, &[0; 8]),
+        mp4_box(b"dinf", &mp4_full_box(b"dref", 0, 0, &dref)),
+        mp4_box(b"stbl", &stbl),
+    ].concat();
+    let mdia = [
+        mp4_full_box(b"mdhd", 0, 0, &mdhd),
+        mp4_full_box(b"hdlr", 0, 0, &hdlr),
+        mp4_box(b"minf", &minf),
+    ].concat();
+    let trak = [
+        mp4_full_box(b"tkhd", 0, 3, &tkhd),
+        mp4_box(b"mdia", &mdia),
+    ].concat();
+    mp4_box(b"moov", &[
+        mp4_full_box(b"mvhd", 0, 0, &mvhd),
+        mp4_box(b"trak", &trak),
+    ].concat())
+}
+
+// Encode RGB frames of the given (even) dimensions into an MP4 file
+pub fn create_mp4(frames: &[Vec<u8>], width: usize, height: usize, fps: u32) -> Vec<u8> {
+    let sps = sps(width, height);
+    let pps = pps();
+    let mut mdat = Vec::new();
+    let mut sample_sizes = Vec::with_capacity(frames.len());
+    for (i, frame) in frames.iter().enumerate() {
+        // Consecutive IDR pictures need different IDs
+        let slice = idr_slice(frame, width, height, (i % 2) as u32);
+        mdat.extend_from_slice(&(slice.len() as u32).to_be_bytes());
+        mdat.extend_from_slice(&slice);
+        sample_sizes.push(slice.len() as u32 + 4);
+    }
+
+    let mut ftyp = Vec::new();
+    ftyp.extend_from_slice(b"isom");
+    ftyp.extend_from_slice(&0x200u32.to_be_bytes());
+    ftyp.extend_from_slice(b"isomiso2avc1mp41");
+    let ftyp = mp4_box(b"ftyp", &ftyp);
+
+    // moov goes first for progressive playback, its size does not depend on the offset value
+    let moov_len = moov(width, height, fps, &sps, &pps, &sample_sizes, 0).len();
+    let chunk_offset = (ftyp.len() + moov_len + 8) as u32;
+    let moov = moov(width, height, fps, &sps, &pps, &sample_sizes, chunk_offset);
+
+    [ftyp, moov, mp4_box(b"mdat", &mdat)].concat()
+}